@@ -0,0 +1,83 @@
+//! Throughput of the remote queue flavours against `std::sync::mpsc`, which they replaced.
+//!
+//! Run with `cargo bench -p dytor --bench remote_queue`.
+
+use std::{
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use dytor::{
+    config::{Backpressure, Queue, WaitStrategy},
+    queue::remote,
+};
+
+const MESSAGES: u64 = 1_000_000;
+
+fn run<Tx: Clone + Send + 'static>(
+    producers: u64,
+    (tx, mut recv): (Tx, impl FnMut() -> Option<u64>),
+    send: fn(&Tx, u64),
+) -> Duration {
+    let start = Instant::now();
+    let handles: Vec<_> = (0..producers)
+        .map(|_| {
+            let tx = tx.clone();
+            thread::spawn(move || {
+                for i in 0..MESSAGES / producers {
+                    send(&tx, i);
+                }
+            })
+        })
+        .collect();
+    drop(tx);
+
+    let mut sum = 0;
+    while let Some(i) = recv() {
+        sum += i;
+    }
+    let elapsed = start.elapsed();
+    assert_eq!(
+        sum,
+        producers * (MESSAGES / producers) * (MESSAGES / producers - 1) / 2
+    );
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    elapsed
+}
+
+fn report(name: &str, producers: u64, elapsed: Duration) {
+    let per_msg = elapsed.as_nanos() as f64 / MESSAGES as f64;
+    println!("{name:<24} {producers} producer(s): {per_msg:>8.1} ns/msg");
+}
+
+fn main() {
+    for producers in [1, 4] {
+        let (tx, rx) = mpsc::channel();
+        let elapsed = run(producers, (tx, move || rx.recv().ok()), |tx, i| {
+            tx.send(i).unwrap()
+        });
+        report("std::sync::mpsc", producers, elapsed);
+
+        for (name, queue, wait_strategy) in [
+            ("remote unbounded", Queue::Unbounded, WaitStrategy::Block),
+            (
+                "remote bounded(1024)",
+                Queue::Bounded {
+                    capacity: 1024,
+                    backpressure: Backpressure::Block,
+                },
+                WaitStrategy::Block,
+            ),
+            ("remote busy-poll", Queue::Unbounded, WaitStrategy::BusyPoll),
+        ] {
+            let (tx, mut rx) = remote::channel(queue, wait_strategy);
+            let elapsed = run(producers, (tx, move || rx.recv()), |tx, i| {
+                tx.send(i).unwrap()
+            });
+            report(name, producers, elapsed);
+        }
+    }
+}
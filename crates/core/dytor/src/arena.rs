@@ -1,11 +1,54 @@
-use std::{alloc::Layout, ptr::NonNull};
+use std::{alloc::Layout, collections::HashMap, ptr::NonNull};
 
 #[derive(Clone, Copy, PartialOrd, Ord, PartialEq, Eq, Hash)]
-pub(crate) struct Offset(pub(crate) u32);
+pub(crate) struct Offset {
+    pub(crate) pos: u32,
+    /// How many actors had the slot before, for an actor spawned after startup; always 0 otherwise
+    pub(crate) generation: u32,
+}
+
+/// Set on offsets of actors spawned after startup, whose `pos` indexes `Overflow::slots` instead
+const OVERFLOW: u32 = 1 << 31;
+
+impl Offset {
+    /// An offset into the arena itself
+    pub(crate) fn new(pos: u32) -> Self {
+        Self { pos, generation: 0 }
+    }
+
+    fn slot(self) -> Option<usize> {
+        (self.pos & OVERFLOW != 0).then_some((self.pos & !OVERFLOW) as usize)
+    }
+}
+
+/// Overflow chunks are at least this large, so spawning many small actors doesn't allocate for each
+const CHUNK_SIZE: usize = 4096;
 
 pub(crate) struct Arena {
     pub(crate) data: NonNull<u8>,
     pub(crate) capacity: u32,
+    overflow: Overflow,
+}
+
+/// Room for actors spawned after startup, since the arena itself is sized for the config's actors
+/// exactly. Actors are bump allocated from chunks that are never moved or freed until the arena
+/// is dropped. The slot of a stopped actor, and so its room, is reused by the next one with the
+/// same layout under the next generation, so a stale offset can't reach the new actor.
+struct Overflow {
+    chunks: Vec<(NonNull<u8>, Layout)>,
+    /// The free end of the newest chunk
+    next: *mut u8,
+    end: *mut u8,
+    slots: Vec<Slot>,
+    /// Slots freed by stopped actors, by their layout
+    free: HashMap<Layout, Vec<usize>>,
+}
+
+struct Slot {
+    room: NonNull<u8>,
+    /// Of the slot's actor, or of its next one once it's freed. `None` once every generation has
+    /// been used, after which its room is moved to a new slot.
+    generation: Option<u32>,
 }
 
 // safety: Arena can be sent as long as nothing has been constructed in it
@@ -41,7 +84,7 @@ fn get_offsets(start: *mut u8, layouts: &[Layout]) -> Vec<Offset> {
     for layout in layouts {
         let addr = curr.wrapping_add(curr.align_offset(layout.align()));
         let offset = unsafe { addr.offset_from(start) }.try_into().unwrap();
-        res.push(Offset(offset));
+        res.push(Offset::new(offset));
         curr = addr.wrapping_add(layout.size());
     }
     res
@@ -49,36 +92,132 @@ fn get_offsets(start: *mut u8, layouts: &[Layout]) -> Vec<Offset> {
 
 impl Arena {
     pub(crate) fn at_offset(&mut self, offset: Offset, layout: Layout) -> &mut [u8] {
-        let ptr = self.data.as_ptr().wrapping_add(offset.0 as usize);
+        let ptr = self.offset(offset);
         assert_eq!(ptr.align_offset(layout.align()), 0);
-        assert!(
-            self.data.as_ptr().wrapping_add(self.capacity as usize)
-                > ptr.wrapping_add(layout.size())
-        );
+        if offset.slot().is_none() {
+            assert!(
+                self.data.as_ptr().wrapping_add(self.capacity as usize)
+                    >= ptr.wrapping_add(layout.size())
+            );
+        }
         unsafe { std::slice::from_raw_parts_mut(ptr, layout.size()) }
     }
 
     pub(crate) fn offset(&self, offset: Offset) -> *mut u8 {
-        self.data.as_ptr().wrapping_add(offset.0 as usize)
+        match offset.slot() {
+            None => self.data.as_ptr().wrapping_add(offset.pos as usize),
+            Some(slot) => self.overflow.slots[slot].room.as_ptr(),
+        }
+    }
+
+    /// Whether `offset` is still that of the actor it was given to, rather than one whose slot
+    /// has been freed since
+    pub(crate) fn is_current(&self, offset: Offset) -> bool {
+        offset
+            .slot()
+            .is_none_or(|slot| self.overflow.slots[slot].generation == Some(offset.generation))
     }
 
     pub(crate) fn from_layouts(layouts: &[Layout]) -> (Arena, Vec<Offset>) {
         let capacity = compute_space_required(layouts);
-        let ptr =
-            unsafe { std::alloc::alloc(Layout::from_size_align(capacity as usize, 1).unwrap()) };
+        let ptr = unsafe { std::alloc::alloc(Self::layout(capacity)) };
         let arena = Arena {
             data: NonNull::new(ptr).unwrap(),
             capacity,
+            overflow: Overflow {
+                chunks: Vec::new(),
+                next: std::ptr::null_mut(),
+                end: std::ptr::null_mut(),
+                slots: Vec::new(),
+                free: HashMap::new(),
+            },
         };
         (arena, get_offsets(ptr, layouts))
     }
+
+    /// Makes room for an actor spawned after startup
+    pub(crate) fn allocate(&mut self, layout: Layout) -> Offset {
+        let overflow = &mut self.overflow;
+        if let Some(slot) = overflow.free.get_mut(&layout).and_then(Vec::pop) {
+            return Offset {
+                pos: slot as u32 | OVERFLOW,
+                generation: overflow.slots[slot].generation.unwrap(),
+            };
+        }
+        let start = if layout.size() >= CHUNK_SIZE {
+            // too large to share a chunk
+            overflow.new_chunk(layout)
+        } else {
+            let start = overflow
+                .next
+                .wrapping_add(overflow.next.align_offset(layout.align()));
+            if overflow.next.is_null() || (overflow.end as usize) < start as usize + layout.size() {
+                let start = overflow.new_chunk(
+                    Layout::from_size_align(CHUNK_SIZE, layout.align().max(16)).unwrap(),
+                );
+                overflow.end = start.wrapping_add(CHUNK_SIZE);
+                overflow.next = start.wrapping_add(layout.size());
+                start
+            } else {
+                overflow.next = start.wrapping_add(layout.size());
+                start
+            }
+        };
+        let room = NonNull::new(start).unwrap();
+        Offset {
+            pos: overflow.new_slot(room) | OVERFLOW,
+            generation: 0,
+        }
+    }
+
+    /// Makes the slot of a spawned actor that has been dropped available to later spawns, after
+    /// which [`Self::is_current`] is false for `offset`. Actors from the config keep theirs.
+    /// Returns whether the slot was freed.
+    pub(crate) fn free(&mut self, offset: Offset, layout: Layout) -> bool {
+        let Some(mut slot) = offset.slot() else {
+            return false;
+        };
+        let overflow = &mut self.overflow;
+        let next = &mut overflow.slots[slot].generation;
+        *next = next.and_then(|generation| generation.checked_add(1));
+        if next.is_none() {
+            let room = overflow.slots[slot].room;
+            slot = overflow.new_slot(room) as usize;
+        }
+        overflow.free.entry(layout).or_default().push(slot);
+        true
+    }
+
+    /// A zero sized allocation isn't allowed, e.g. for a context whose actors are all zero sized
+    fn layout(capacity: u32) -> Layout {
+        Layout::from_size_align(capacity.max(1) as usize, 1).unwrap()
+    }
+}
+
+impl Overflow {
+    fn new_slot(&mut self, room: NonNull<u8>) -> u32 {
+        let slot = u32::try_from(self.slots.len()).unwrap();
+        assert!(slot < OVERFLOW, "too many actors spawned on one context");
+        self.slots.push(Slot {
+            room,
+            generation: Some(0),
+        });
+        slot
+    }
+
+    fn new_chunk(&mut self, layout: Layout) -> *mut u8 {
+        let ptr = NonNull::new(unsafe { std::alloc::alloc(layout) }).unwrap();
+        self.chunks.push((ptr, layout));
+        ptr.as_ptr()
+    }
 }
 
 impl Drop for Arena {
     fn drop(&mut self) {
-        let ptr = self.data.as_ptr();
-        let layout = Layout::from_size_align(self.capacity as usize, 1).unwrap();
-        unsafe { std::alloc::dealloc(ptr, layout) };
+        unsafe { std::alloc::dealloc(self.data.as_ptr(), Self::layout(self.capacity)) };
+        for &(ptr, layout) in &self.overflow.chunks {
+            unsafe { std::alloc::dealloc(ptr.as_ptr(), layout) };
+        }
     }
 }
 
@@ -101,4 +240,57 @@ mod tests {
         );
         assert_eq!(space, 23);
     }
+
+    #[test]
+    fn exactly_full() {
+        let layout = Layout::new::<u8>();
+        let (mut arena, offsets) = Arena::from_layouts(&[layout]);
+        assert_eq!(arena.capacity, 1);
+        assert_eq!(arena.at_offset(offsets[0], layout).len(), 1);
+    }
+
+    #[test]
+    fn overflow() {
+        let (mut arena, _) = Arena::from_layouts(&[]);
+        let layouts = [(1, 1), (8, 8), (0, 4), (CHUNK_SIZE, 16), (4, 4)]
+            .map(|(s, a)| Layout::from_size_align(s, a).unwrap());
+        let offsets = layouts.map(|layout| arena.allocate(layout));
+        for (offset, layout) in offsets.into_iter().zip(layouts) {
+            assert_eq!(arena.at_offset(offset, layout).len(), layout.size());
+        }
+        // the large actor gets a chunk to itself
+        assert_eq!(arena.overflow.chunks.len(), 2);
+        let addr = |i: usize| arena.offset(offsets[i]) as usize;
+        assert_eq!(addr(1), addr(0) + 8);
+        assert_eq!(addr(4), addr(1) + 8);
+
+        // a freed actor's slot is reused, under a new generation
+        let freed = addr(1);
+        assert!(arena.free(offsets[1], layouts[1]));
+        assert!(!arena.is_current(offsets[1]));
+        let reused = arena.allocate(layouts[1]);
+        assert!(reused != offsets[1] && arena.is_current(reused));
+        assert_eq!(arena.offset(reused) as usize, freed);
+        assert_eq!(arena.overflow.slots.len(), layouts.len());
+    }
+
+    #[test]
+    fn slot_retired() {
+        let (mut arena, _) = Arena::from_layouts(&[]);
+        let layout = Layout::new::<u64>();
+        let first = arena.allocate(layout);
+        arena.overflow.slots[0].generation = Some(u32::MAX);
+        let last = Offset {
+            generation: u32::MAX,
+            ..first
+        };
+        let room = arena.offset(last);
+        // no generation is left for the slot, so its room moves to a new one
+        arena.free(last, layout);
+        let reused = arena.allocate(layout);
+        assert_eq!(reused.slot(), Some(1));
+        assert_eq!(arena.offset(reused), room);
+        assert!(!arena.is_current(last));
+        assert!(!arena.is_current(first));
+    }
 }
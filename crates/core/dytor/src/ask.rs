@@ -0,0 +1,454 @@
+//! Request/response messaging: [`crate::MainArgs::ask`] runs a closure on the target actor and
+//! delivers its result to the caller as a separate message, on the caller's context.
+//!
+//! `on_reply` runs exactly once per ask, unless it's cancelled first. If the request is discarded
+//! without running, because the target has stopped, a full queue dropped it or the target
+//! panicked while handling it, `on_reply` gets [`AskError::Dropped`]. That notice skips the
+//! backpressure policy of the caller's queue, but is lost if the queue is full; an ask with a
+//! timeout still times out then.
+
+use std::{
+    fmt,
+    marker::PhantomData,
+    ptr::{NonNull, Pointee},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    context::{Context, ContextData, ControlBlock, MsgTx, QueueItem},
+    lookup::Key,
+    message::{Msg, Origin},
+    timer::{self, TimerHandle},
+    MainArgs,
+};
+
+struct AskState {
+    done: AtomicBool,
+    /// Armed on the caller's context by an ask with a timeout
+    timer: OnceLock<TimerHandle>,
+}
+
+impl AskState {
+    /// Returns whether the ask was still pending, in which case the caller decides its outcome
+    fn finish(&self) -> bool {
+        !self.done.swap(true, Ordering::Relaxed)
+    }
+
+    fn is_done(&self) -> bool {
+        self.done.load(Ordering::Relaxed)
+    }
+}
+
+/// Refers to an outstanding [`crate::MainArgs::ask`]. Dropping it doesn't cancel the request.
+#[derive(Clone)]
+pub struct AskHandle(Arc<AskState>);
+
+impl AskHandle {
+    /// Stops the reply from being delivered. If the target hasn't run the request yet, it won't.
+    /// Cancelling from the context the ask was made on also disarms its timeout; from anywhere else
+    /// the timer stays armed until it's due, and does nothing then.
+    ///
+    /// Returns `false` if the reply has already been delivered, or the ask already timed out or
+    /// was cancelled. Once this returns `true` the `on_reply` continuation is guaranteed not to
+    /// run.
+    pub fn cancel(&self, args: &mut MainArgs) -> bool {
+        if !self.0.finish() {
+            return false;
+        }
+        if let Some(&timer) = self.0.timer.get() {
+            args.cancel_timer(timer);
+        }
+        true
+    }
+
+    /// Whether the reply hasn't been delivered yet, nor the ask timed out or been cancelled
+    pub fn is_pending(&self) -> bool {
+        !self.0.is_done()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum AskError {
+    /// No reply arrived within the timeout. The request isn't run if the target hasn't got round
+    /// to it by then.
+    TimedOut,
+    /// The request was discarded without a reply: the target had stopped, a full queue dropped
+    /// the request, or the target panicked while handling it
+    Dropped,
+}
+
+impl fmt::Display for AskError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AskError::TimedOut => write!(f, "ask timed out"),
+            AskError::Dropped => write!(f, "ask was dropped without a reply"),
+        }
+    }
+}
+
+impl std::error::Error for AskError {}
+
+/// Whatever settles the ask first, the reply, a timeout or a [`Request`] being dropped, calls
+/// `on_reply`; the rest do nothing
+struct Reply<C: ?Sized, R, F> {
+    state: Arc<AskState>,
+    reply_to: Key<C>,
+    on_reply: Mutex<Option<F>>,
+    _phantom: PhantomData<fn(R)>,
+}
+
+impl<C: ?Sized, R, F> Reply<C, R, F>
+where
+    F: FnOnce(&mut MainArgs, &mut C, Result<R, AskError>),
+{
+    fn deliver(&self, args: &mut MainArgs, caller: &mut C, result: Result<R, AskError>) {
+        if !self.state.finish() {
+            return;
+        }
+        if let Some(&timer) = self.state.timer.get() {
+            args.cancel_timer(timer);
+        }
+        let on_reply = self.on_reply.lock().unwrap().take().unwrap();
+        on_reply(args, caller, result);
+    }
+}
+
+/// The message run on the target. It sends the reply back to `reply_to` with an ordinary
+/// `send_msg`, so replies take the same local or cross-context path as any other message.
+struct Request<C: ?Sized + 'static, R: 'static + Send, F, G>
+where
+    <C as Pointee>::Metadata: 'static,
+    F: 'static + Send + FnOnce(&mut MainArgs, &mut C, Result<R, AskError>),
+{
+    f: Option<G>,
+    reply: Arc<Reply<C, R, F>>,
+    /// Set once the reply has been sent
+    answered: bool,
+    /// The queue of `reply_to`'s context, for reporting the request as dropped from wherever that
+    /// happens
+    tx: MsgTx,
+    /// Held by the request, so it can count the notice it sends when dropped
+    block: NonNull<ControlBlock>,
+}
+
+// safety: the control block is only touched atomically, and the request holds it until dropped
+unsafe impl<C: ?Sized, R: Send, F, G: Send> Send for Request<C, R, F, G>
+where
+    <C as Pointee>::Metadata: 'static,
+    F: 'static + Send + FnOnce(&mut MainArgs, &mut C, Result<R, AskError>),
+{
+}
+
+impl<C: ?Sized, R: Send, F, G> Request<C, R, F, G>
+where
+    <C as Pointee>::Metadata: 'static,
+    F: 'static + Send + FnOnce(&mut MainArgs, &mut C, Result<R, AskError>),
+{
+    fn run<T: ?Sized>(mut self, args: &mut MainArgs, target: &mut T)
+    where
+        G: FnOnce(&mut MainArgs, &mut T) -> R,
+    {
+        let f = self.f.take().unwrap();
+        if self.reply.state.is_done() {
+            return;
+        }
+        let result = f(args, target);
+        let reply = self.reply.clone();
+        args.send_msg(self.reply.reply_to, move |args, caller| {
+            reply.deliver(args, caller, Ok(result))
+        });
+        self.answered = true;
+    }
+}
+
+impl<C: ?Sized, R: Send, F, G> Drop for Request<C, R, F, G>
+where
+    <C as Pointee>::Metadata: 'static,
+    F: 'static + Send + FnOnce(&mut MainArgs, &mut C, Result<R, AskError>),
+{
+    fn drop(&mut self) {
+        if !self.answered && !self.reply.state.is_done() {
+            // this may run on any context, even the caller's own with its queue full, so it
+            // mustn't wait for room
+            let reply = self.reply.clone();
+            let Key { loc, meta } = reply.reply_to;
+            let msg = Msg::new(move |ctx: &mut Context| {
+                ctx.call_actor(loc.offset, meta, |args, caller| {
+                    reply.deliver(args, caller, Err(AskError::Dropped))
+                })
+            });
+            // the message being dropped still counts as unhandled, so this can't reach zero
+            let block = unsafe { self.block.as_ref() };
+            block.unhandled_events.fetch_add(1, Ordering::Relaxed);
+            if self.tx.try_send(QueueItem::Msg(msg)).is_err() {
+                block.unhandled_events.fetch_sub(1, Ordering::Relaxed);
+            }
+        }
+        // safety: the request took a hold on the block when it was made
+        unsafe { ControlBlock::release_holder(self.block) };
+    }
+}
+
+impl ContextData {
+    /// Sends `f` to `target` as a [`Request`]. With a timeout, a timer on this context settles the
+    /// ask with [`AskError::TimedOut`], by sending a message if `reply_to` is on another one.
+    pub(crate) fn ask<T: ?Sized, C: ?Sized + 'static, R: 'static + Send>(
+        &mut self,
+        target: Key<T>,
+        timeout: Option<Duration>,
+        f: impl 'static + Send + FnOnce(&mut MainArgs, &mut T) -> R,
+        reply_to: Key<C>,
+        on_reply: impl 'static + Send + FnOnce(&mut MainArgs, &mut C, Result<R, AskError>),
+    ) -> AskHandle
+    where
+        <T as Pointee>::Metadata: 'static,
+        <C as Pointee>::Metadata: 'static,
+    {
+        let state = Arc::new(AskState {
+            done: AtomicBool::new(false),
+            timer: OnceLock::new(),
+        });
+        let reply = Arc::new(Reply {
+            state: state.clone(),
+            reply_to,
+            on_reply: Mutex::new(Some(on_reply)),
+            _phantom: PhantomData,
+        });
+        if let Some(timeout) = timeout {
+            let deadline = Instant::now() + timeout;
+            let reply = reply.clone();
+            let timer = if reply_to.loc.context_id == self.id {
+                self.schedule(reply_to, deadline, None, move |args, c| {
+                    reply.deliver(args, c, Err(AskError::TimedOut))
+                })
+            } else {
+                // the caller is on another context, so the timeout is passed on as a message
+                let mut reply = Some(reply);
+                let callback: timer::Callback = Box::new(move |ctx: &mut Context| {
+                    let reply = reply.take().unwrap();
+                    if reply.state.is_done() {
+                        return;
+                    }
+                    let Key { loc, meta } = reply.reply_to;
+                    let mut msg = Msg::new(move |ctx: &mut Context| {
+                        ctx.call_actor(loc.offset, meta, |args, caller| {
+                            reply.deliver(args, caller, Err(AskError::TimedOut))
+                        })
+                    });
+                    msg.origin = Origin::Timer;
+                    ctx.data.current_actor = None;
+                    ctx.data.enqueue(loc.context_id, msg);
+                });
+                let id = self.timers.insert(deadline, None, None, callback);
+                TimerHandle {
+                    context: self.id,
+                    id,
+                }
+            };
+            let _ = state.timer.set(timer);
+        }
+
+        let block = self.control_block;
+        unsafe { block.as_ref() }.add_holder();
+        let request = Request {
+            f: Some(f),
+            reply,
+            answered: false,
+            tx: (self.make_tx[reply_to.loc.context_id.as_index()])(),
+            block,
+        };
+        self.send_msg(target, move |args, target| request.run(args, target));
+        AskHandle(state)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Mutex, thread, time::Duration};
+
+    use super::*;
+    use crate::{
+        config::Supervision,
+        lookup::Dependency,
+        register_actor,
+        runtime::{test::config, try_run},
+        Actor, InitArgs, UniquelyNamed,
+    };
+
+    static EVENTS: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+
+    fn record(scenario: &str, event: impl Into<String>) {
+        EVENTS.lock().unwrap().push((scenario.into(), event.into()));
+    }
+
+    fn events(scenario: &str) -> Vec<String> {
+        let events = EVENTS.lock().unwrap();
+        events
+            .iter()
+            .filter(|(s, _)| s == scenario)
+            .map(|(_, e)| e.clone())
+            .collect()
+    }
+
+    /// Asks the answerer for its answer in the way its config names
+    struct Asker {
+        scenario: String,
+        me: Key<Asker>,
+        answerer: Key<Answerer>,
+        /// Another asker, on another context
+        peer: Option<Key<Asker>>,
+    }
+
+    impl UniquelyNamed for Asker {
+        fn name() -> &'static str {
+            "Asker"
+        }
+    }
+
+    register_actor!(Asker);
+
+    impl Actor for Asker {
+        type Config = String;
+
+        fn init(mut args: InitArgs<Self>, scenario: String) -> anyhow::Result<Self> {
+            let me = args.key();
+            Ok(Self {
+                scenario,
+                me,
+                answerer: args.query().exactly_one_key(),
+                peer: args
+                    .query::<Asker>()
+                    .all_keys()
+                    .find(|key| key.loc.context_id != me.loc.context_id),
+            })
+        }
+
+        fn on_start(&mut self, args: &mut MainArgs) {
+            let scenario = self.scenario.clone();
+            let answer = move |_: &mut MainArgs, answerer: &mut Answerer| {
+                record(&scenario, "answered");
+                answerer.answer
+            };
+            let on_reply = |_: &mut MainArgs, asker: &mut Asker, reply: Result<u32, AskError>| {
+                record(&asker.scenario, format!("{reply:?}"))
+            };
+            match self.scenario.as_str() {
+                "timeout" => {
+                    args.send_msg(self.answerer, |_, _| {
+                        thread::sleep(Duration::from_millis(200))
+                    });
+                    let timeout = Some(Duration::from_millis(20));
+                    args.ask_timeout(self.answerer, timeout, answer, self.me, on_reply);
+                }
+                "cancel" => {
+                    let timeout = Some(Duration::from_secs(60));
+                    let handle =
+                        args.ask_timeout(self.answerer, timeout, answer, self.me, on_reply);
+                    assert!(handle.cancel(args));
+                    assert!(!handle.cancel(args) && !handle.is_pending());
+                }
+                "stopped" => {
+                    args.send_msg(self.answerer, |_, _| panic!("answerer stopped on purpose"));
+                    args.ask(self.answerer, answer, self.me, on_reply);
+                }
+                "relay" => {
+                    args.send_msg(self.answerer, |_, _| {
+                        thread::sleep(Duration::from_millis(200))
+                    });
+                    // the timeout's timer is on this context, the peer on another
+                    let scenario = self.scenario.clone();
+                    let on_reply =
+                        move |_: &mut MainArgs, peer: &mut Asker, reply: Result<u32, AskError>| {
+                            record(&scenario, format!("{reply:?} to {}", peer.scenario))
+                        };
+                    let timeout = Some(Duration::from_millis(20));
+                    let peer = self.peer.unwrap();
+                    args.ask_timeout(self.answerer, timeout, answer, peer, on_reply);
+                }
+                "idle" => {}
+                _ => {
+                    args.ask(self.answerer, answer, self.me, on_reply);
+                }
+            }
+        }
+
+        fn dependencies() -> Vec<Dependency> {
+            vec![Dependency::any::<Answerer>(), Dependency::any::<Asker>()]
+        }
+    }
+
+    struct Answerer {
+        answer: u32,
+    }
+
+    impl UniquelyNamed for Answerer {
+        fn name() -> &'static str {
+            "Answerer"
+        }
+    }
+
+    register_actor!(Answerer);
+
+    impl Actor for Answerer {
+        type Config = ();
+
+        fn init(_args: InitArgs<Self>, _config: ()) -> anyhow::Result<Self> {
+            Ok(Self { answer: 42 })
+        }
+    }
+
+    fn run(scenario: &str, answerer_context: u32, supervision: Option<Supervision>) {
+        let mut config = config(&[("Asker", 1), ("Answerer", answerer_context)]);
+        config.root.actors[0].config = serde_value::Value::String(scenario.into());
+        config.root.actors[1].supervision = supervision;
+        try_run(config).unwrap();
+    }
+
+    #[test]
+    fn local() {
+        run("local", 1, None);
+        assert_eq!(events("local"), ["answered", "Ok(42)"]);
+    }
+
+    #[test]
+    fn cross_context() {
+        run("remote", 2, None);
+        assert_eq!(events("remote"), ["answered", "Ok(42)"]);
+    }
+
+    #[test]
+    fn timeout() {
+        // the answerer's context is busy until well after the deadline, so it skips the request
+        run("timeout", 2, None);
+        assert_eq!(events("timeout"), ["Err(TimedOut)"]);
+    }
+
+    #[test]
+    fn timeout_to_another_context() {
+        let mut config = config(&[("Asker", 1), ("Answerer", 3), ("Asker", 2)]);
+        config.root.actors[0].config = serde_value::Value::String("relay".into());
+        config.root.actors[2].config = serde_value::Value::String("idle".into());
+        try_run(config).unwrap();
+        assert_eq!(events("relay"), ["Err(TimedOut) to idle"]);
+    }
+
+    #[test]
+    fn cancel() {
+        // the system would stay up for the whole timeout if cancelling left the timer armed
+        let start = Instant::now();
+        run("cancel", 2, None);
+        assert!(start.elapsed() < Duration::from_secs(30));
+        assert!(events("cancel").is_empty());
+    }
+
+    #[test]
+    fn stopped_target() {
+        run("stopped", 2, Some(Supervision::Stop));
+        assert_eq!(events("stopped"), ["Err(Dropped)"]);
+    }
+}
@@ -5,28 +5,172 @@ use serde::Deserialize;
 use crate::context::ContextId;
 
 #[derive(Deserialize)]
+#[cfg_attr(
+    feature = "schema",
+    derive(schemars::JsonSchema),
+    schemars(rename = "DytorActorConfig")
+)]
 pub struct ActorConfig {
     pub typename: Arc<str>,
+    /// Optional instance name, which queries can select by with [`crate::lookup::Query::named`]
+    #[serde(default)]
+    pub name: Option<Arc<str>>,
+    /// Deserialized as the `Config` of the actor type named by `typename`
+    #[cfg_attr(feature = "schema", schemars(with = "serde_json::Value"))]
     pub config: serde_value::Value,
     pub context: ContextId,
+    /// What happens when one of the actor's handlers panics. Without a policy, the panic unwinds
+    /// through its context's thread, as it would anywhere else.
+    #[serde(default)]
+    pub supervision: Option<Supervision>,
+}
+
+/// How an actor recovers from a panic in one of its handlers: a message, a timer, or
+/// [`crate::Actor::on_start`] or [`crate::Actor::on_stop`]. The panic is caught where the handler
+/// was called, after which the context carries on with the next message.
+///
+/// The actor is dropped without running `on_stop`, as its state may be inconsistent. Messages sent
+/// to an actor that has stopped are dropped without being handled, and reported to the
+/// [`crate::dead_letter::DeadLetterSink`] if there is one.
+///
+/// A panic is blamed on the actor whose handler was called, or on the local dependency it was
+/// using through [`crate::lookup::AcyclicLocalKey::call`] at the time. Code using a reference from
+/// `AcyclicLocalKey::borrow_mut` runs as the caller, so its panics are blamed on the caller.
+///
+/// Only panics that unwind can be caught; with `panic = "abort"` the process still aborts.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(
+    feature = "schema",
+    derive(schemars::JsonSchema),
+    schemars(rename = "DytorSupervision")
+)]
+pub enum Supervision {
+    /// Run `init` again in place, with the same config, then `on_start`. The actor stops if
+    /// either fails.
+    Restart,
+    /// Stop the actor
+    Stop,
+    /// Stop the actor and shut the whole system down, not just the actor's context, since contexts
+    /// only shut down together. The other actors stop as in any shutdown, running `on_stop`.
+    Escalate,
 }
 
+/// Actors in a scope can find actors declared in the same scope, in any of its ancestors and in any
+/// of its `imported_scopes`. Children are named by their key in `children`; an import is a
+/// `.`-separated path whose first component is resolved against the children of this scope, then
+/// against the children of each ancestor in turn.
 #[derive(Deserialize)]
+#[cfg_attr(
+    feature = "schema",
+    derive(schemars::JsonSchema),
+    schemars(rename = "DytorScope")
+)]
 pub struct Scope {
+    #[serde(default)]
     pub name: Option<Arc<str>>,
+    #[serde(default)]
     pub children: HashMap<Arc<str>, Scope>,
     pub actors: Vec<ActorConfig>,
+    #[serde(default)]
     pub imported_scopes: Vec<Arc<str>>,
 }
 
 #[derive(Deserialize)]
+#[cfg_attr(
+    feature = "schema",
+    derive(schemars::JsonSchema),
+    schemars(rename = "DytorContext")
+)]
 pub struct Context {
     pub id: ContextId,
+    /// CPUs the context's thread is pinned to before any of its actors are constructed
+    #[serde(default)]
     pub thread_affinity: Option<Vec<usize>>,
+    #[serde(default)]
+    pub queue: Queue,
+    /// How the context's thread waits for messages when its queue is empty
+    #[serde(default)]
+    pub wait_strategy: WaitStrategy,
+}
+
+/// The queue other contexts and accessors send to a context through
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+#[cfg_attr(
+    feature = "schema",
+    derive(schemars::JsonSchema),
+    schemars(rename = "DytorQueue")
+)]
+pub enum Queue {
+    /// Grows as needed, a block of messages at a time
+    #[default]
+    Unbounded,
+    /// A preallocated ring, rounded up to a power of two of at least 2
+    Bounded {
+        capacity: usize,
+        #[serde(default)]
+        backpressure: Backpressure,
+    },
+}
+
+/// What happens to a message sent to a full bounded queue. Messages that are dropped count towards
+/// the queue's [`crate::metrics::QueueMetrics`].
+///
+/// This applies to [`crate::Accessor::send`] and to messages other contexts send with `send_msg`,
+/// `tell` or `broadcast`. Those are only sent once the handler that sent them returns, so `reject`
+/// can't be reported to them and drops the message like `drop_newest`, but is counted as a
+/// rejection. Under `block`, two contexts with full queues sending to each other deadlock.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(
+    feature = "schema",
+    derive(schemars::JsonSchema),
+    schemars(rename = "DytorBackpressure")
+)]
+pub enum Backpressure {
+    /// Wait for space
+    #[default]
+    Block,
+    /// Drop the message being sent
+    DropNewest,
+    /// Drop the oldest queued message to make room
+    DropOldest,
+    /// Fail with [`crate::queue::remote::SendError::Full`] when sent through an accessor; drop
+    /// the message when sent from another context
+    Reject,
 }
 
 #[derive(Deserialize)]
+#[cfg_attr(
+    feature = "schema",
+    derive(schemars::JsonSchema),
+    schemars(rename = "DytorConfig")
+)]
 pub struct Config {
     pub root: Scope,
     pub contexts: Vec<Context>,
+    /// Shut the system down gracefully on SIGINT or SIGTERM, as [`crate::ShutdownHandle::shutdown`]
+    /// does
+    #[serde(default)]
+    pub shutdown_on_signals: bool,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+#[cfg_attr(
+    feature = "schema",
+    derive(schemars::JsonSchema),
+    schemars(rename = "DytorWaitStrategy")
+)]
+pub enum WaitStrategy {
+    /// Park the thread until a message arrives
+    #[default]
+    Block,
+    /// Poll `spins` times before parking, trading some CPU for lower wake-up latency
+    SpinThenPark { spins: u32 },
+    /// Poll, yielding the thread between attempts
+    Yield,
+    /// Poll in a tight loop, keeping a core busy for the lowest latency
+    BusyPoll,
 }
@@ -1,9 +1,9 @@
 use std::{
     alloc::Layout,
     any::{Any, TypeId},
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, HashSet},
     marker::PhantomData,
-    mem::{self, MaybeUninit},
+    mem::MaybeUninit,
     num::NonZeroU32,
     ops::{Deref, DerefMut},
     ptr::{self, NonNull, Pointee},
@@ -11,24 +11,47 @@ use std::{
         atomic::{fence, AtomicU32, Ordering},
         Arc, LazyLock,
     },
+    time::{Duration, Instant},
 };
 
 use serde::Deserialize;
 
 use crate::{
     arena::{Arena, Offset},
-    lookup::{ActorTree, BroadcastGroup, DependenceRelation, Key, Lookup, Query},
-    queue::remote,
+    ask::{AskError, AskHandle},
+    dead_letter::{DeadLetter, DeadLetterSink, Sender},
+    lookup::{
+        ActorTree, BroadcastGroup, DependenceRelation, Filter, Key, LiveTree, Loc, Lookup, Query,
+    },
+    message::{Handler, Msg, Origin},
+    metrics::QueueMetrics,
+    object::{Hook, VTable},
+    queue::{
+        local::{LocalQueue, Slab},
+        remote::{self, SendError},
+    },
+    registry::Registry,
+    runtime::{
+        supervision::{self, Supervisor},
+        ActorConstructorInfo, ShutdownHandle,
+    },
+    timer::{self, TimerHandle, TimerWheel},
+    Error,
 };
 
 type PhantomUnsend = PhantomData<*mut ()>;
 
 #[repr(transparent)]
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub(crate) struct ActorId(pub(crate) NonZeroU32);
+pub struct ActorId(pub(crate) NonZeroU32);
 
 #[repr(transparent)]
 #[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[cfg_attr(
+    feature = "schema",
+    derive(schemars::JsonSchema),
+    schemars(rename = "DytorContextId")
+)]
 pub struct ContextId(pub(crate) NonZeroU32);
 
 macro_rules! impl_inner_ops {
@@ -56,42 +79,225 @@ macro_rules! impl_inner_ops {
 impl_inner_ops!(ActorId);
 impl_inner_ops!(ContextId);
 
-type LocalQueue = crate::queue::local::LocalQueue<Box<dyn FnOnce(&mut Context)>>;
-
 pub(crate) struct ContextData {
     pub(crate) id: ContextId,
-    pub(crate) local_queue: LocalQueue,
-    pub(crate) unsent_messages: Vec<(ContextId, Msg)>,
+    pub(crate) local_queue: LocalQueue<Msg>,
+    /// Messages to other contexts, indexed by context, held until the current handler returns
+    pub(crate) unsent_messages: Box<[LocalQueue<Msg>]>,
+    pub(crate) unsent_len: usize,
+    /// Backs the local queue and unsent messages
+    pub(crate) slab: Slab<Msg>,
+    pub(crate) timers: TimerWheel,
+    pub(crate) shutdown: ShutdownHandle,
+    /// The actor most recently called, which a panic is blamed on
+    pub(crate) current_actor: Option<Offset>,
+    /// Actors that haven't been constructed yet, or have been dropped after a panic, see
+    /// [`crate::config::Supervision`], or by [`MainArgs::stop`]. A spawned actor is taken out
+    /// again once its slot in the arena is freed, as the arena knows its offset is stale.
+    pub(crate) stopped: HashSet<Offset>,
+    /// Set by the message [`MainArgs::stop`] sends, to stop its target once it returns
+    pub(crate) stopping: bool,
+    /// Who sent the message or fired the timer being handled
+    pub(crate) origin: Origin,
+    pub(crate) live_tree: Arc<LiveTree>,
+    /// Actors spawned by the current handler, constructed once it returns
+    pub(crate) spawned: Vec<ActorConstructorInfo>,
+    /// The registry as it was when the system started, which actors are spawned from
+    pub(crate) registry: &'static Registry,
+    /// For constructing actors after startup, when restarting or spawning them, and for
+    /// replying to asks
+    pub(crate) make_tx: Arc<[Box<dyn Fn() -> MsgTx + Send + Sync>]>,
+    /// Held by the context, so valid until it exits
+    pub(crate) control_block: NonNull<ControlBlock>,
 }
 
-type DropFn = unsafe fn(*mut u8);
-
 // TODO: move this to runtime module
 pub struct Context {
     pub(crate) data: ContextData,
     pub(crate) arena: Arena,
-    pub(crate) drop_fns: Vec<(Offset, DropFn)>,
+    pub(crate) constructed: Constructed,
     pub(crate) rx: MsgRx,
     pub(crate) links: Box<[ContextLink]>,
+    /// Only if any actor on this context is supervised
+    pub(crate) supervisor: Option<Box<Supervisor>>,
+    pub(crate) resources: Arc<HashMap<TypeId, LazyResource>>,
     pub(crate) _unsend_marker: PhantomUnsend,
 }
 
+impl Context {
+    /// Calls `f` on the actor at `offset`, unless it has stopped, in which case `f` is dropped
+    /// and reported as a dead letter
+    pub(crate) fn call_actor<T: ?Sized>(
+        &mut self,
+        offset: Offset,
+        meta: <T as Pointee>::Metadata,
+        f: impl FnOnce(&mut MainArgs, &mut T),
+    ) {
+        if self.data.is_stopped(&self.arena, offset) {
+            self.dead_letter(offset);
+            return;
+        }
+        self.data.current_actor = Some(offset);
+        let ptr = ptr::from_raw_parts_mut::<T>(self.arena.offset(offset) as *mut (), meta);
+        let mut args = MainArgs {
+            context_data: &mut self.data,
+            arena: &mut self.arena,
+            actor: offset,
+        };
+        f(&mut args, unsafe { &mut *ptr });
+        if !self.data.spawned.is_empty() {
+            self.start_spawned();
+        }
+        if self.data.stopping {
+            self.stop_actor(offset);
+        }
+    }
+
+    /// Runs `on_start` or `on_stop` on the actor at `offset`, unless it has stopped
+    pub(crate) fn run_hook(&mut self, offset: Offset, hook: fn(&VTable) -> Hook) {
+        let Some(vtable) = self.constructed.vtable(offset) else {
+            return;
+        };
+        if self.data.is_stopped(&self.arena, offset) {
+            return;
+        }
+        self.data.current_actor = Some(offset);
+        let ptr = self.arena.offset(offset);
+        let mut args = MainArgs {
+            context_data: &mut self.data,
+            arena: &mut self.arena,
+            actor: offset,
+        };
+        unsafe { hook(vtable)(ptr, &mut args) };
+        if !self.data.spawned.is_empty() {
+            self.start_spawned();
+        }
+    }
+
+    /// The actor dead letters are reported to, looked up each time, since it may have been
+    /// spawned since startup
+    pub(crate) fn dead_letter_sink(tree: &ActorTree) -> Option<Key<dyn DeadLetterSink>> {
+        let (_, sink) = Lookup::<dyn DeadLetterSink, _>::lookup(tree, None).next()?;
+        Some(sink)
+    }
+
+    fn dead_letter(&mut self, offset: Offset) {
+        let tree = self.data.live_tree.snapshot();
+        let Some(sink) = Self::dead_letter_sink(&tree) else {
+            return;
+        };
+        let target = Loc {
+            context_id: self.data.id,
+            offset,
+        };
+        // the sink has stopped too, so reporting this would only make another dead letter
+        if sink.loc == target {
+            return;
+        }
+        // a spawned actor is forgotten once another has stopped in its slot since
+        let Some(target) = tree.actor_at(target) else {
+            return;
+        };
+        let sender = match self.data.origin {
+            Origin::Actor(loc) => {
+                let Some(actor) = tree.actor_at(loc) else {
+                    return;
+                };
+                Sender::Actor {
+                    typename: (actor.vtable.name)(),
+                    actor: actor.id,
+                }
+            }
+            Origin::Accessor => Sender::Accessor,
+            Origin::Timer => Sender::Timer,
+        };
+        let letter = DeadLetter {
+            typename: (target.vtable.name)(),
+            actor: target.id,
+            sender,
+        };
+        self.data
+            .send_msg(sink, move |args, sink| sink.dead_letter(args, letter));
+    }
+
+    pub(crate) fn handle_local(&mut self) {
+        while let Some(msg) = self.data.local_queue.recv(&mut self.data.slab) {
+            supervision::run(self, |ctx| msg.run(ctx));
+        }
+    }
+}
+
 impl Drop for Context {
     fn drop(&mut self) {
-        for &(offset, drop) in &self.drop_fns {
-            let ptr = self.arena.offset(offset);
-            unsafe { drop(ptr) };
+        for (offset, vtable) in self.constructed.iter().rev() {
+            if !self.data.is_stopped(&self.arena, offset) {
+                let ptr = self.arena.offset(offset);
+                unsafe { (vtable.drop)(ptr) };
+            }
         }
     }
 }
 
-pub enum QueueItem {
+/// The actors constructed on a context, in construction order; they're stopped and dropped in
+/// reverse so dependents go first. A spawned actor is taken out once it stops, so its slot can be
+/// reused.
+#[derive(Default)]
+pub(crate) struct Constructed {
+    /// By when each was constructed
+    order: BTreeMap<u64, (Offset, &'static VTable)>,
+    by_offset: HashMap<Offset, u64>,
+    next: u64,
+}
+
+impl Constructed {
+    /// Adds an actor, unless it's being constructed again after a restart, in which case it keeps
+    /// its place
+    pub(crate) fn push(&mut self, offset: Offset, vtable: &'static VTable) {
+        if self.by_offset.contains_key(&offset) {
+            return;
+        }
+        self.order.insert(self.next, (offset, vtable));
+        self.by_offset.insert(offset, self.next);
+        self.next += 1;
+    }
+
+    pub(crate) fn remove(&mut self, offset: Offset) {
+        if let Some(i) = self.by_offset.remove(&offset) {
+            self.order.remove(&i);
+        }
+    }
+
+    pub(crate) fn vtable(&self, offset: Offset) -> Option<&'static VTable> {
+        Some(self.order[self.by_offset.get(&offset)?].1)
+    }
+
+    pub(crate) fn iter(&self) -> impl DoubleEndedIterator<Item = (Offset, &'static VTable)> + '_ {
+        self.order.values().copied()
+    }
+
+    /// In construction order, for running hooks that may construct or stop actors
+    pub(crate) fn offsets(&self) -> Vec<Offset> {
+        self.order.values().map(|&(offset, _)| offset).collect()
+    }
+}
+
+impl FromIterator<(Offset, &'static VTable)> for Constructed {
+    fn from_iter<I: IntoIterator<Item = (Offset, &'static VTable)>>(iter: I) -> Self {
+        let mut constructed = Self::default();
+        for (offset, vtable) in iter {
+            constructed.push(offset, vtable);
+        }
+        constructed
+    }
+}
+
+pub(crate) enum QueueItem {
     Msg(Msg),
     AccessorDropped,
-    Stop,
+    /// Sent to every other context once this one has run its `on_stop` hooks
+    Stopped,
 }
 
-pub(crate) type Msg = Box<dyn 'static + Send + FnOnce(&mut Context)>;
 pub(crate) type MsgRx = remote::Rx<QueueItem>;
 pub(crate) type MsgTx = remote::Tx<QueueItem>;
 
@@ -99,11 +305,55 @@ pub(crate) struct ContextLink {
     pub(crate) queue: MsgTx,
 }
 
+impl MsgTx {
+    /// Sends `msg` under the receiving queue's backpressure policy, returning how many messages
+    /// were dropped to apply it. Each of those held an unhandled event, which the caller releases.
+    pub(crate) fn send_msg(&self, msg: Msg) -> (u32, Result<(), SendError>) {
+        let mut dropped = 0;
+        let mut evicted = Vec::new();
+        let res = self.send_with_backpressure(QueueItem::Msg(msg), |item| match item {
+            QueueItem::Msg(_) => dropped += 1,
+            // control items are never dropped; putting one back out of order is harmless since it
+            // can't make the count of unhandled events reach zero early
+            item => evicted.push(item),
+        });
+        for item in evicted {
+            let _ = self.send(item);
+        }
+        self.metrics()
+            .record_dropped(self.backpressure(), dropped as u64);
+        (dropped, res)
+    }
+}
+
 pub(crate) struct InitData {
     pub(crate) data: ContextData,
     pub(crate) tree: Arc<ActorTree>,
     pub(crate) dependence_relations: Vec<DependenceRelation>,
-    pub(crate) make_tx: Arc<[Box<dyn Fn() -> MsgTx + Send + Sync>]>,
+    pub(crate) arena: Arena,
+    /// In construction order
+    pub(crate) constructed: Vec<(Offset, &'static VTable)>,
+    pub(crate) pending: HashMap<ActorId, ActorConstructorInfo>,
+    pub(crate) error: Option<Error>,
+    /// Set when the actor being constructed is handed an [`crate::lookup::AcyclicLocalKey`] to
+    /// an actor that wasn't constructed, so it's dropped rather than started
+    pub(crate) dependency_missing: bool,
+}
+
+impl InitData {
+    pub(crate) fn accessor_for_key<T: ?Sized>(
+        &self,
+        control_block_ptr: &ControlBlockPtr,
+        key: Key<T>,
+    ) -> Accessor<T> {
+        Accessor {
+            offset: key.loc.offset,
+            metadata: key.meta,
+            ctx_queue: (self.make_tx[key.loc.context_id.as_index()])(),
+            control_block_ptr: control_block_ptr.for_accessor(),
+            _phantom: PhantomData,
+        }
+    }
 }
 
 type SharedAny = Box<dyn Send + Sync + Any>;
@@ -136,33 +386,76 @@ impl<'a, ActorT> InitArgs<'a, ActorT> {
     pub fn query<T: ?Sized>(&mut self) -> Query<'_, 'a, T, ActorT> {
         Query {
             init_args: self,
+            filter: Filter::default(),
             phantom: PhantomData,
         }
     }
 
+    /// Runs `f` on the actor as a message. Messages to the same actor are handled in the order
+    /// they're sent; see [`crate::queue`] for the full guarantee.
+    ///
+    /// A message to another context is sent once the current handler returns, so there's nobody
+    /// to fail: if that context's bounded queue is full and rejects messages, the message is
+    /// dropped as under `drop_newest`, and counted as rejected. See
+    /// [`crate::config::Backpressure`].
     pub fn send_msg<T: ?Sized>(
         &mut self,
-        Key { loc, meta }: Key<T>,
+        key: Key<T>,
         f: impl 'static + Send + FnOnce(&mut MainArgs, &mut T),
     ) where
         <T as Pointee>::Metadata: 'static,
     {
-        let f = Box::new(move |ctx: &mut Context| {
-            let ptr = ctx.arena.offset(loc.offset);
-            let ptr = ptr::from_raw_parts_mut(ptr as *mut (), meta);
-            let mut args = MainArgs {
-                context_data: &mut ctx.data,
-                arena: &ctx.arena,
-            };
-            f(&mut args, unsafe { &mut *ptr });
-        });
-        if self.data.id == loc.context_id {
-            self.data.local_queue.send(f)
-        } else {
-            self.data.unsent_messages.push((loc.context_id, f));
-        }
+        self.data.send_msg(key, f)
+    }
+
+    /// Sends `msg` to an actor that implements [`Handler<M>`], without allocating unless `msg` is
+    /// larger than 48 bytes; see [`crate::message`]. A full queue that rejects messages drops it,
+    /// as with [`Self::send_msg`].
+    pub fn tell<T: ?Sized + Handler<M>, M: 'static + Send>(&mut self, key: Key<T>, msg: M)
+    where
+        <T as Pointee>::Metadata: 'static,
+    {
+        self.data.tell(key, msg)
+    }
+
+    /// Runs `f` on `target`, then `on_reply` on `reply_to` with its result, as a message on
+    /// `reply_to`'s context. Usually `reply_to` is the caller's own key. `on_reply` runs exactly
+    /// once unless the ask is cancelled, with [`AskError::Dropped`] if the request is discarded
+    /// without running; see [`crate::ask`].
+    pub fn ask<T: ?Sized, C: ?Sized + 'static, R: 'static + Send>(
+        &mut self,
+        target: Key<T>,
+        f: impl 'static + Send + FnOnce(&mut MainArgs, &mut T) -> R,
+        reply_to: Key<C>,
+        on_reply: impl 'static + Send + FnOnce(&mut MainArgs, &mut C, Result<R, AskError>),
+    ) -> AskHandle
+    where
+        <T as Pointee>::Metadata: 'static,
+        <C as Pointee>::Metadata: 'static,
+    {
+        self.ask_timeout(target, None, f, reply_to, on_reply)
     }
 
+    /// Like [`Self::ask`], but `on_reply` gets [`AskError::TimedOut`] if no reply has arrived
+    /// within `timeout`. The timeout is a timer on this context, which tells `reply_to` with a
+    /// message if it's on another one.
+    pub fn ask_timeout<T: ?Sized, C: ?Sized + 'static, R: 'static + Send>(
+        &mut self,
+        target: Key<T>,
+        timeout: Option<Duration>,
+        f: impl 'static + Send + FnOnce(&mut MainArgs, &mut T) -> R,
+        reply_to: Key<C>,
+        on_reply: impl 'static + Send + FnOnce(&mut MainArgs, &mut C, Result<R, AskError>),
+    ) -> AskHandle
+    where
+        <T as Pointee>::Metadata: 'static,
+        <C as Pointee>::Metadata: 'static,
+    {
+        self.data.ask(target, timeout, f, reply_to, on_reply)
+    }
+
+    /// Runs `f` on every actor in `group`, as a message each. A full queue that rejects messages
+    /// drops its actors' copies, as with [`Self::send_msg`].
     pub fn broadcast<T: ?Sized>(
         &mut self,
         group: &BroadcastGroup<T>,
@@ -181,26 +474,29 @@ impl<'a, ActorT> InitArgs<'a, ActorT> {
 }
 
 impl<ActorT: 'static> InitArgs<'_, ActorT> {
+    /// The key of the actor being constructed, e.g. to pass as `reply_to` to [`Self::ask`]
+    pub fn key(&self) -> Key<ActorT> {
+        Key {
+            loc: Loc {
+                context_id: self.data.id,
+                offset: self.actor_offset,
+            },
+            meta: (),
+        }
+    }
+
     pub fn accessor(&self) -> Accessor<ActorT> {
-        mem::forget(self.control_block_ptr.clone());
         Accessor {
             offset: self.actor_offset,
             metadata: (),
             ctx_queue: (self.data.make_tx[self.data.id.as_index()])(),
-            control_block_ptr: self.control_block_ptr.0,
+            control_block_ptr: self.control_block_ptr.for_accessor(),
             _phantom: PhantomData,
         }
     }
 
     pub fn accessor_for_key<T: 'static + ?Sized>(&self, key: Key<T>) -> Accessor<T> {
-        mem::forget(self.control_block_ptr.clone());
-        Accessor {
-            offset: key.loc.offset,
-            metadata: key.meta,
-            ctx_queue: (self.data.make_tx[key.loc.context_id.as_index()])(),
-            control_block_ptr: self.control_block_ptr.0,
-            _phantom: PhantomData,
-        }
+        self.data.accessor_for_key(self.control_block_ptr, key)
     }
 }
 
@@ -261,33 +557,79 @@ generate_impl!(A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V,
 
 pub struct MainArgs<'a> {
     pub(crate) context_data: &'a mut ContextData,
-    pub(crate) arena: &'a Arena,
+    /// Only the arena's bookkeeping; actors live in memory it points to
+    pub(crate) arena: &'a mut Arena,
+    /// The actor being called
+    pub(crate) actor: Offset,
 }
 
 impl MainArgs<'_> {
+    /// Runs `f` on the actor as a message. Messages to the same actor are handled in the order
+    /// they're sent; see [`crate::queue`] for the full guarantee.
+    ///
+    /// A message to another context is sent once the current handler returns, so there's nobody
+    /// to fail: if that context's bounded queue is full and rejects messages, the message is
+    /// dropped as under `drop_newest`, and counted as rejected. See
+    /// [`crate::config::Backpressure`].
     pub fn send_msg<T: ?Sized>(
         &mut self,
-        Key { loc, meta }: Key<T>,
+        key: Key<T>,
         f: impl 'static + Send + FnOnce(&mut MainArgs, &mut T),
     ) where
         <T as Pointee>::Metadata: 'static,
     {
-        let f = Box::new(move |ctx: &mut Context| {
-            let ptr = ctx.arena.offset(loc.offset);
-            let ptr = ptr::from_raw_parts_mut(ptr as *mut (), meta);
-            let mut args = MainArgs {
-                context_data: &mut ctx.data,
-                arena: &ctx.arena,
-            };
-            f(&mut args, unsafe { &mut *ptr });
-        });
-        if self.context_data.id == loc.context_id {
-            self.context_data.local_queue.send(f)
-        } else {
-            self.context_data.unsent_messages.push((loc.context_id, f));
-        }
+        self.context_data.send_msg(key, f)
+    }
+
+    /// Sends `msg` to an actor that implements [`Handler<M>`], without allocating unless `msg` is
+    /// larger than 48 bytes; see [`crate::message`]. A full queue that rejects messages drops it,
+    /// as with [`Self::send_msg`].
+    pub fn tell<T: ?Sized + Handler<M>, M: 'static + Send>(&mut self, key: Key<T>, msg: M)
+    where
+        <T as Pointee>::Metadata: 'static,
+    {
+        self.context_data.tell(key, msg)
+    }
+
+    /// Runs `f` on `target`, then `on_reply` on `reply_to` with its result, as a message on
+    /// `reply_to`'s context. Usually `reply_to` is the caller's own key. `on_reply` runs exactly
+    /// once unless the ask is cancelled, with [`AskError::Dropped`] if the request is discarded
+    /// without running; see [`crate::ask`].
+    pub fn ask<T: ?Sized, C: ?Sized + 'static, R: 'static + Send>(
+        &mut self,
+        target: Key<T>,
+        f: impl 'static + Send + FnOnce(&mut MainArgs, &mut T) -> R,
+        reply_to: Key<C>,
+        on_reply: impl 'static + Send + FnOnce(&mut MainArgs, &mut C, Result<R, AskError>),
+    ) -> AskHandle
+    where
+        <T as Pointee>::Metadata: 'static,
+        <C as Pointee>::Metadata: 'static,
+    {
+        self.ask_timeout(target, None, f, reply_to, on_reply)
+    }
+
+    /// Like [`Self::ask`], but `on_reply` gets [`AskError::TimedOut`] if no reply has arrived
+    /// within `timeout`. The timeout is a timer on this context, which tells `reply_to` with a
+    /// message if it's on another one.
+    pub fn ask_timeout<T: ?Sized, C: ?Sized + 'static, R: 'static + Send>(
+        &mut self,
+        target: Key<T>,
+        timeout: Option<Duration>,
+        f: impl 'static + Send + FnOnce(&mut MainArgs, &mut T) -> R,
+        reply_to: Key<C>,
+        on_reply: impl 'static + Send + FnOnce(&mut MainArgs, &mut C, Result<R, AskError>),
+    ) -> AskHandle
+    where
+        <T as Pointee>::Metadata: 'static,
+        <C as Pointee>::Metadata: 'static,
+    {
+        self.context_data
+            .ask(target, timeout, f, reply_to, on_reply)
     }
 
+    /// Runs `f` on every actor in `group`, as a message each. A full queue that rejects messages
+    /// drops its actors' copies, as with [`Self::send_msg`].
     pub fn broadcast<T: ?Sized>(
         &mut self,
         group: &BroadcastGroup<T>,
@@ -297,9 +639,132 @@ impl MainArgs<'_> {
     {
         self.context_data.broadcast(group, f)
     }
+
+    /// Shuts the system down once the current handler returns, like
+    /// [`crate::ShutdownHandle::shutdown`]
+    pub fn request_shutdown(&self) {
+        self.context_data.shutdown.shutdown()
+    }
+
+    /// Calls `f` on `key` once `delay` has passed. `key` must be on this context.
+    pub fn schedule_after<T: ?Sized>(
+        &mut self,
+        key: Key<T>,
+        delay: Duration,
+        f: impl 'static + FnOnce(&mut MainArgs, &mut T),
+    ) -> TimerHandle
+    where
+        <T as Pointee>::Metadata: 'static,
+    {
+        self.schedule_at(key, Instant::now() + delay, f)
+    }
+
+    /// Calls `f` on `key` once `deadline` has passed. `key` must be on this context.
+    pub fn schedule_at<T: ?Sized>(
+        &mut self,
+        key: Key<T>,
+        deadline: Instant,
+        f: impl 'static + FnOnce(&mut MainArgs, &mut T),
+    ) -> TimerHandle
+    where
+        <T as Pointee>::Metadata: 'static,
+    {
+        let mut f = Some(f);
+        self.context_data
+            .schedule(key, deadline, None, move |args, t| {
+                (f.take().unwrap())(args, t)
+            })
+    }
+
+    /// Calls `f` on `key` every `period`, starting one period from now, until the timer is
+    /// cancelled. `key` must be on this context.
+    pub fn schedule_every<T: ?Sized>(
+        &mut self,
+        key: Key<T>,
+        period: Duration,
+        f: impl 'static + FnMut(&mut MainArgs, &mut T),
+    ) -> TimerHandle
+    where
+        <T as Pointee>::Metadata: 'static,
+    {
+        assert!(!period.is_zero(), "schedule_every needs a non-zero period");
+        self.context_data
+            .schedule(key, Instant::now() + period, Some(period), f)
+    }
+
+    /// Returns `false` if the timer has already fired, was already cancelled or belongs to another
+    /// context. A periodic timer can cancel itself from its own callback.
+    pub fn cancel_timer(&mut self, handle: TimerHandle) -> bool {
+        handle.context == self.context_data.id && self.context_data.timers.cancel(handle.id)
+    }
 }
 
 impl ContextData {
+    pub(crate) fn schedule<T: ?Sized>(
+        &mut self,
+        Key { loc, meta }: Key<T>,
+        deadline: Instant,
+        period: Option<Duration>,
+        mut f: impl 'static + FnMut(&mut MainArgs, &mut T),
+    ) -> TimerHandle
+    where
+        <T as Pointee>::Metadata: 'static,
+    {
+        let context = self.id;
+        assert_eq!(
+            loc.context_id, context,
+            "timers can only call actors on the context that schedules them"
+        );
+        let callback: timer::Callback = Box::new(move |ctx: &mut Context| {
+            ctx.data.origin = Origin::Timer;
+            ctx.call_actor(loc.offset, meta, &mut f);
+        });
+        let id = self
+            .timers
+            .insert(deadline, period, Some(loc.offset), callback);
+        TimerHandle { context, id }
+    }
+
+    pub(crate) fn send_msg<T: ?Sized>(
+        &mut self,
+        Key { loc, meta }: Key<T>,
+        f: impl 'static + Send + FnOnce(&mut MainArgs, &mut T),
+    ) where
+        <T as Pointee>::Metadata: 'static,
+    {
+        let msg = Msg::new(move |ctx: &mut Context| ctx.call_actor(loc.offset, meta, f));
+        self.enqueue(loc.context_id, msg);
+    }
+
+    pub(crate) fn tell<T: ?Sized + Handler<M>, M: 'static + Send>(
+        &mut self,
+        Key { loc, meta }: Key<T>,
+        msg: M,
+    ) where
+        <T as Pointee>::Metadata: 'static,
+    {
+        self.enqueue(loc.context_id, Msg::tell::<T, M>(loc.offset, meta, msg));
+    }
+
+    pub(crate) fn is_stopped(&self, arena: &Arena, offset: Offset) -> bool {
+        (!self.stopped.is_empty() && self.stopped.contains(&offset)) || !arena.is_current(offset)
+    }
+
+    pub(crate) fn enqueue(&mut self, context_id: ContextId, mut msg: Msg) {
+        if let Some(offset) = self.current_actor {
+            msg.origin = Origin::Actor(Loc {
+                context_id: self.id,
+                offset,
+            });
+        }
+        if self.id == context_id {
+            self.local_queue.send(&mut self.slab, msg)
+        } else {
+            self.unsent_messages[context_id.as_index()].send(&mut self.slab, msg);
+            self.unsent_len += 1;
+        }
+    }
+
     pub fn broadcast<T: ?Sized>(
         &mut self,
         group: &BroadcastGroup<T>,
@@ -308,42 +773,51 @@ impl ContextData {
         <T as Pointee>::Metadata: 'static,
     {
         for (id, refs) in group.by_context.as_ref() {
-            if *id == self.id {
-                let refs = refs.clone();
-                let f = f.clone();
-                self.local_queue.send(Box::new(move |ctx: &mut Context| {
-                    let mut ms = MainArgs {
-                        context_data: &mut ctx.data,
-                        arena: &ctx.arena,
-                    };
-                    for (offset, meta) in refs.as_ref() {
-                        let ptr =
-                            ptr::from_raw_parts_mut(ctx.arena.offset(*offset) as *mut (), *meta);
-                        f(&mut ms, unsafe { &mut *ptr });
-                    }
-                }));
-            } else {
-                let refs = refs.clone();
-                let f = f.clone();
-                let msg = Box::new(move |ctx: &mut Context| {
-                    let mut ms = MainArgs {
-                        context_data: &mut ctx.data,
-                        arena: &ctx.arena,
-                    };
-                    for (offset, meta) in refs.as_ref() {
-                        let ptr = ctx.arena.offset(*offset);
-                        let ptr = ptr::from_raw_parts_mut(ptr as *mut (), *meta);
-                        f(&mut ms, unsafe { &mut *ptr });
-                    }
-                });
-                self.unsent_messages.push((*id, msg));
-            }
+            let refs = refs.clone();
+            let f = f.clone();
+            // one message per context, but each call is supervised on its own so a recipient that
+            // panics doesn't keep the rest from running
+            let msg = Msg::new(move |ctx: &mut Context| {
+                for &(offset, meta) in refs.as_ref() {
+                    supervision::run(ctx, |ctx| ctx.call_actor(offset, meta, &f));
+                }
+            });
+            self.enqueue(*id, msg);
         }
     }
 }
 
 pub(crate) struct ControlBlock {
     pub(crate) unhandled_events: AtomicU32,
+    /// Running contexts and live accessors, any of which may still touch the block. It's freed
+    /// by whichever lets go last, which may be after the system has shut down.
+    holders: AtomicU32,
+}
+
+impl ControlBlock {
+    /// Takes an unhandled event and a hold on the block, which the new accessor gives back when
+    /// it's dropped
+    pub(crate) fn add_accessor(&self) {
+        self.unhandled_events.fetch_add(1, Ordering::Relaxed);
+        self.holders.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A hold on the block for something other than an accessor, given back with
+    /// [`Self::release_holder`]
+    pub(crate) fn add_holder(&self) {
+        self.holders.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Safety: the caller must be one of the block's holders, and not touch it afterwards
+    pub(crate) unsafe fn release_holder(ptr: NonNull<ControlBlock>) {
+        let block = unsafe { ptr.as_ref() };
+        if block.holders.fetch_sub(1, Ordering::Release) > 1 {
+            return;
+        }
+        fence(Ordering::Acquire);
+        let layout = Layout::for_value(block);
+        unsafe { std::alloc::dealloc(ptr.as_ptr() as *mut _, layout) };
+    }
 }
 
 pub(crate) struct ControlBlockPtr(pub(crate) NonNull<ControlBlock>);
@@ -351,9 +825,11 @@ pub(crate) struct ControlBlockPtr(pub(crate) NonNull<ControlBlock>);
 unsafe impl Send for ControlBlockPtr {}
 
 impl ControlBlockPtr {
-    pub(crate) fn new() -> Self {
+    /// Each of the `contexts` holds the block until it has stopped
+    pub(crate) fn new(contexts: u32) -> Self {
         let block = ControlBlock {
             unhandled_events: AtomicU32::new(1),
+            holders: AtomicU32::new(contexts),
         };
         let layout = Layout::for_value(&block);
         let ptr = unsafe { std::alloc::alloc(layout) } as *mut MaybeUninit<ControlBlock>;
@@ -363,24 +839,27 @@ impl ControlBlockPtr {
         Self(ptr)
     }
 
+    /// Returns whether this was the last unhandled event
     pub(crate) fn release(self) -> bool {
-        let ptr = self.0.as_ptr();
-        let block = unsafe { &*ptr };
+        let block = unsafe { self.0.as_ref() };
         std::mem::forget(self);
         if block.unhandled_events.fetch_sub(1, Ordering::Release) > 1 {
             return false;
         }
-
         fence(Ordering::Acquire);
-        let layout = Layout::for_value(&block);
-        unsafe { std::alloc::dealloc(ptr as *mut _, layout) };
         true
     }
 
-    pub(crate) fn into_unowned(self) -> NonNull<ControlBlock> {
+    /// Also returns whether this was the last unhandled event
+    pub(crate) fn into_unowned(self) -> (NonNull<ControlBlock>, bool) {
         let res = self.0;
-        self.release();
-        res
+        (res, self.release())
+    }
+
+    /// An unhandled event and a hold on the block, for a new accessor
+    pub(crate) fn for_accessor(&self) -> NonNull<ControlBlock> {
+        unsafe { self.0.as_ref() }.add_accessor();
+        self.0
     }
 }
 
@@ -411,28 +890,49 @@ pub struct Accessor<T: ?Sized> {
 
 impl<T: ?Sized> Drop for Accessor<T> {
     fn drop(&mut self) {
-        self.ctx_queue.send(QueueItem::AccessorDropped).unwrap();
+        // the receiving context may already be gone if the system failed to start or shut down
+        let _ = self.ctx_queue.send(QueueItem::AccessorDropped);
+        unsafe { ControlBlock::release_holder(self.control_block_ptr) };
     }
 }
 
 unsafe impl<T: ?Sized> Send for Accessor<T> {}
 
 impl<T: ?Sized + 'static> Accessor<T> {
-    pub fn send(&self, f: impl 'static + Send + FnOnce(&mut MainArgs, &mut T)) {
+    /// Fails with [`SendError::Full`] if the actor's queue is full and rejects messages, or with
+    /// [`SendError::Disconnected`] if its context has stopped. A message dropped by any other
+    /// backpressure policy still counts as sent.
+    pub fn send(
+        &self,
+        f: impl 'static + Send + FnOnce(&mut MainArgs, &mut T),
+    ) -> Result<(), SendError> {
         let offset = self.offset;
         let metadata = self.metadata;
-        let queued_fn = Box::new(move |ctx: &mut Context| {
-            let ptr = ctx.arena.offset(offset);
-            let ptr = ptr::from_raw_parts_mut(ptr as *mut (), metadata);
-            let mut ms = MainArgs {
-                context_data: &mut ctx.data,
-                arena: &ctx.arena,
-            };
-            f(&mut ms, unsafe { &mut *ptr });
-        });
-        unsafe { self.control_block_ptr.as_ref() }
-            .unhandled_events
-            .fetch_add(1, Ordering::Relaxed);
-        self.ctx_queue.send(QueueItem::Msg(queued_fn)).unwrap()
+        let queued_fn = Msg::new(move |ctx: &mut Context| ctx.call_actor(offset, metadata, f));
+        self.enqueue(queued_fn)
+    }
+
+    /// Like [`Self::send`], for an actor that implements [`Handler<M>`]. The message is only boxed
+    /// if it's larger than 48 bytes; see [`crate::message`].
+    pub fn tell<M: 'static + Send>(&self, msg: M) -> Result<(), SendError>
+    where
+        T: Handler<M>,
+        <T as Pointee>::Metadata: 'static,
+    {
+        self.enqueue(Msg::tell::<T, M>(self.offset, self.metadata, msg))
+    }
+
+    fn enqueue(&self, msg: Msg) -> Result<(), SendError> {
+        let block = unsafe { self.control_block_ptr.as_ref() };
+        block.unhandled_events.fetch_add(1, Ordering::Relaxed);
+        let (dropped, res) = self.ctx_queue.send_msg(msg);
+        // this accessor holds an event of its own, so the count can't reach zero here
+        block.unhandled_events.fetch_sub(dropped, Ordering::Relaxed);
+        res
+    }
+
+    /// Metrics of the queue of the context the actor is on
+    pub fn queue_metrics(&self) -> &QueueMetrics {
+        self.ctx_queue.metrics()
     }
 }
@@ -0,0 +1,367 @@
+//! Messages that can't be delivered because their target has stopped, see
+//! [`crate::config::Supervision`] and [`crate::MainArgs::stop`]. Each is reported to the first
+//! actor registered with the sink trait, e.g. `register_actor!(Postmaster { dyn DeadLetterSink })`,
+//! or dropped silently if there is none. The sink is looked up as each letter is reported, so a
+//! sink spawned after startup is used once no actor in the config is one.
+//!
+//! The sink is also told about spawned actors that never started; see [`crate::MainArgs::spawn`].
+//!
+//! A spawned actor's room in its context's arena is reused once it stops. Letters to or from it
+//! are reported until the next actor given its room stops too, and dropped silently after that.
+
+use std::fmt;
+
+use crate::{context::ActorId, MainArgs};
+
+/// Told about every message sent to a stopped actor. Messages sent to the sink itself once it
+/// has stopped are dropped without a report.
+pub trait DeadLetterSink {
+    fn dead_letter(&mut self, args: &mut MainArgs, letter: DeadLetter);
+
+    /// Told about every spawned actor whose `init` failed or panicked. Ignored by default.
+    fn spawn_failed(&mut self, _args: &mut MainArgs, _failure: SpawnFailure) {}
+}
+
+/// Describes a message that was dropped, rather than run, because its target had stopped
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DeadLetter {
+    /// The target's registered typename
+    pub typename: &'static str,
+    pub actor: ActorId,
+    pub sender: Sender,
+}
+
+/// Describes a spawned actor that was taken out of the system without starting
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SpawnFailure {
+    /// The actor's registered typename
+    pub typename: &'static str,
+    pub actor: ActorId,
+    pub reason: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sender {
+    /// An actor, from its `init`, a hook or a handler
+    Actor {
+        typename: &'static str,
+        actor: ActorId,
+    },
+    /// An [`crate::Accessor`], from outside the system
+    Accessor,
+    /// A timer the target scheduled for itself
+    Timer,
+}
+
+impl fmt::Display for DeadLetter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "message from {} to stopped actor {} #{}",
+            self.sender,
+            self.typename,
+            self.actor.as_u32()
+        )
+    }
+}
+
+impl fmt::Display for SpawnFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "spawned actor {} #{}: {}",
+            self.typename,
+            self.actor.as_u32(),
+            self.reason
+        )
+    }
+}
+
+impl fmt::Display for Sender {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Actor { typename, actor } => write!(f, "actor {typename} #{}", actor.as_u32()),
+            Self::Accessor => f.write_str("an accessor"),
+            Self::Timer => f.write_str("a timer"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Mutex, thread, time::Duration};
+
+    use super::*;
+    use crate::{
+        config::Supervision,
+        register_actor,
+        runtime::{test::config, Runtime},
+        Actor, Handler, InitArgs, UniquelyNamed,
+    };
+
+    /// By the label of the sink that was told
+    static LETTERS: Mutex<Vec<(String, DeadLetter)>> = Mutex::new(Vec::new());
+
+    fn letters(sink: &str) -> Vec<DeadLetter> {
+        let letters = LETTERS.lock().unwrap();
+        letters
+            .iter()
+            .filter(|(s, _)| s == sink)
+            .map(|(_, letter)| *letter)
+            .collect()
+    }
+
+    /// By the label of the sink that was told
+    static FAILURES: Mutex<Vec<(String, SpawnFailure)>> = Mutex::new(Vec::new());
+
+    /// Panics when told to, which stops it; the config says whether it tells itself on startup
+    struct Mortal;
+
+    impl UniquelyNamed for Mortal {
+        fn name() -> &'static str {
+            "Mortal"
+        }
+    }
+
+    register_actor!(Mortal);
+
+    struct Die;
+    struct Ping;
+
+    impl Handler<Die> for Mortal {
+        fn handle(&mut self, _args: &mut MainArgs, _msg: Die) {
+            panic!("mortal died on purpose");
+        }
+    }
+
+    impl Handler<Ping> for Mortal {
+        fn handle(&mut self, _args: &mut MainArgs, _msg: Ping) {}
+    }
+
+    impl Actor for Mortal {
+        type Config = bool;
+
+        fn init(mut args: InitArgs<Self>, die_at_start: bool) -> anyhow::Result<Self> {
+            if die_at_start {
+                let key = args.key();
+                args.tell(key, Die);
+                args.tell(key, Ping);
+            }
+            Ok(Self)
+        }
+    }
+
+    struct Postmaster {
+        label: String,
+    }
+
+    impl UniquelyNamed for Postmaster {
+        fn name() -> &'static str {
+            "Postmaster"
+        }
+    }
+
+    register_actor!(Postmaster { dyn DeadLetterSink });
+
+    impl Actor for Postmaster {
+        type Config = String;
+
+        fn init(_args: InitArgs<Self>, label: String) -> anyhow::Result<Self> {
+            Ok(Self { label })
+        }
+    }
+
+    impl DeadLetterSink for Postmaster {
+        fn dead_letter(&mut self, _args: &mut MainArgs, letter: DeadLetter) {
+            LETTERS.lock().unwrap().push((self.label.clone(), letter));
+        }
+
+        fn spawn_failed(&mut self, _args: &mut MainArgs, failure: SpawnFailure) {
+            FAILURES.lock().unwrap().push((self.label.clone(), failure));
+        }
+    }
+
+    /// Spawns the sink when told to
+    struct Founder;
+
+    impl UniquelyNamed for Founder {
+        fn name() -> &'static str {
+            "Founder"
+        }
+    }
+
+    register_actor!(Founder);
+
+    struct Found;
+
+    impl Handler<Found> for Founder {
+        fn handle(&mut self, args: &mut MainArgs, _msg: Found) {
+            args.spawn::<Postmaster>("spawned".into());
+        }
+    }
+
+    impl Actor for Founder {
+        type Config = ();
+
+        fn init(_args: InitArgs<Self>, _config: ()) -> anyhow::Result<Self> {
+            Ok(Self)
+        }
+    }
+
+    /// Fails to construct, by panicking if its config says so
+    struct Stillborn;
+
+    impl UniquelyNamed for Stillborn {
+        fn name() -> &'static str {
+            "Stillborn"
+        }
+    }
+
+    register_actor!(Stillborn);
+
+    impl Handler<Ping> for Stillborn {
+        fn handle(&mut self, _args: &mut MainArgs, _msg: Ping) {}
+    }
+
+    impl Actor for Stillborn {
+        type Config = bool;
+
+        fn init(_args: InitArgs<Self>, panics: bool) -> anyhow::Result<Self> {
+            if panics {
+                panic!("doomed on purpose");
+            }
+            anyhow::bail!("doomed")
+        }
+    }
+
+    /// Spawns a `Stillborn` of each kind when told to, and pings them
+    struct Summoner;
+
+    impl UniquelyNamed for Summoner {
+        fn name() -> &'static str {
+            "Summoner"
+        }
+    }
+
+    register_actor!(Summoner);
+
+    struct Summon;
+
+    impl Handler<Summon> for Summoner {
+        fn handle(&mut self, args: &mut MainArgs, _msg: Summon) {
+            for panics in [false, true] {
+                let doomed = args.spawn::<Stillborn>(panics);
+                args.tell(doomed, Ping);
+            }
+        }
+    }
+
+    impl Actor for Summoner {
+        type Config = ();
+
+        fn init(_args: InitArgs<Self>, _config: ()) -> anyhow::Result<Self> {
+            Ok(Self)
+        }
+    }
+
+    fn letter(sender: Sender) -> DeadLetter {
+        DeadLetter {
+            typename: "Mortal",
+            actor: ActorId::new(1).unwrap(),
+            sender,
+        }
+    }
+
+    #[test]
+    fn reported_to_sink() {
+        let mut config = config(&[("Mortal", 1), ("Postmaster", 2)]);
+        config.root.actors[0].config = serde_value::Value::Bool(true);
+        config.root.actors[0].supervision = Some(Supervision::Stop);
+        config.root.actors[1].config = serde_value::Value::String("config".into());
+        let handle = Runtime::start(config).unwrap();
+        let mortal = handle.accessors::<Mortal>().next().unwrap();
+        mortal.tell(Ping).unwrap();
+        drop(mortal);
+        handle.join();
+
+        // the ping it told itself in `init`, then the one from outside
+        let from_itself = Sender::Actor {
+            typename: "Mortal",
+            actor: ActorId::new(1).unwrap(),
+        };
+        assert_eq!(
+            letters("config"),
+            [letter(from_itself), letter(Sender::Accessor)]
+        );
+    }
+
+    #[test]
+    fn reported_to_spawned_sink() {
+        let mut config = config(&[("Mortal", 1), ("Founder", 2)]);
+        config.root.actors[0].config = serde_value::Value::Bool(false);
+        config.root.actors[0].supervision = Some(Supervision::Stop);
+        let handle = Runtime::start(config).unwrap();
+        handle
+            .accessors::<Founder>()
+            .next()
+            .unwrap()
+            .tell(Found)
+            .unwrap();
+        while handle.accessors::<Postmaster>().next().is_none() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        let mortal = handle.accessors::<Mortal>().next().unwrap();
+        mortal.tell(Die).unwrap();
+        mortal.tell(Ping).unwrap();
+        drop(mortal);
+        handle.join();
+
+        assert_eq!(letters("spawned"), [letter(Sender::Accessor)]);
+    }
+
+    #[test]
+    fn spawn_failures_reported() {
+        let mut config = config(&[("Postmaster", 1), ("Summoner", 1)]);
+        config.root.actors[0].config = serde_value::Value::String("summoned".into());
+        let handle = Runtime::start(config).unwrap();
+        let summoner = handle.accessors::<Summoner>().next().unwrap();
+        summoner.tell(Summon).unwrap();
+        drop(summoner);
+        while letters("summoned").len() < 2 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        // neither is left in the actor tree
+        assert_eq!(handle.accessors::<Stillborn>().count(), 0);
+        handle.join();
+
+        let failures: Vec<_> = FAILURES
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(s, _)| s == "summoned")
+            .map(|(_, failure)| failure.clone())
+            .collect();
+        let failure = |actor, reason: &str| SpawnFailure {
+            typename: "Stillborn",
+            actor: ActorId::new(actor).unwrap(),
+            reason: reason.into(),
+        };
+        assert_eq!(
+            failures,
+            [
+                failure(3, "init failed: doomed"),
+                failure(4, "init panicked: doomed on purpose")
+            ]
+        );
+        let summoner = Sender::Actor {
+            typename: "Summoner",
+            actor: ActorId::new(2).unwrap(),
+        };
+        let ping = |actor| DeadLetter {
+            typename: "Stillborn",
+            actor: ActorId::new(actor).unwrap(),
+            sender: summoner,
+        };
+        assert_eq!(letters("summoned"), [ping(3), ping(4)]);
+    }
+}
@@ -0,0 +1,161 @@
+use std::{fmt, io, path::PathBuf, sync::Arc};
+
+use crate::ContextId;
+
+/// Identifies an actor in the config passed to [`crate::try_run`]
+#[derive(Debug, Clone)]
+pub struct ActorInfo {
+    /// Position of the actor in its scope's `actors` list
+    pub index: usize,
+    pub typename: Arc<str>,
+    /// `.`-separated path of the scope declaring the actor
+    pub scope: String,
+    pub context: ContextId,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// There must be at least one context, with ids numbered `1..=n` in order
+    InvalidContextIds,
+    InvalidThreadAffinity {
+        context: ContextId,
+        reason: String,
+    },
+    /// A bounded queue must have room for at least one message
+    InvalidQueueCapacity(ContextId),
+    /// Handlers for `shutdown_on_signals` couldn't be installed
+    SignalHandlers(io::Error),
+    UnknownScopeImport {
+        scope: String,
+        import: Arc<str>,
+    },
+    UnknownContext(ActorInfo),
+    UnknownTypename(ActorInfo),
+    InvalidConfig {
+        actor: ActorInfo,
+        source: anyhow::Error,
+    },
+    InitFailed {
+        actor: ActorInfo,
+        source: anyhow::Error,
+    },
+    /// A dependency declared by [`crate::Actor::dependencies`] can't be satisfied
+    Dependency {
+        actor: ActorInfo,
+        dependency: &'static str,
+        reason: String,
+    },
+    /// `cycle` starts and ends with the same actor
+    Cycle {
+        context: ContextId,
+        cycle: Vec<ActorInfo>,
+    },
+    ReadConfig {
+        path: PathBuf,
+        source: io::Error,
+    },
+    ParseConfig {
+        path: PathBuf,
+        reason: String,
+    },
+    LoadLibrary {
+        path: PathBuf,
+        reason: String,
+    },
+    /// `path` is the library whose registrations failed, or `None` for ones made by the executable
+    Registration {
+        path: Option<PathBuf>,
+        source: anyhow::Error,
+    },
+}
+
+impl fmt::Display for ActorInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self {
+            index,
+            typename,
+            scope,
+            context,
+        } = self;
+        write!(
+            f,
+            "actor #{index} ({typename}) in scope {scope} on context {}",
+            context.0
+        )
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidContextIds => write!(f, "Contexts are not numbered as 1 ..= n"),
+            Error::InvalidThreadAffinity { context, reason } => {
+                write!(
+                    f,
+                    "Invalid thread_affinity for context {}: {reason}",
+                    context.0
+                )
+            }
+            Error::InvalidQueueCapacity(context) => {
+                write!(f, "Queue of context {} has zero capacity", context.0)
+            }
+            Error::SignalHandlers(source) => {
+                write!(f, "Could not install signal handlers: {source}")
+            }
+            Error::UnknownScopeImport { scope, import } => {
+                write!(f, "Scope {scope} imports unknown scope {import}")
+            }
+            Error::UnknownContext(actor) => write!(f, "{actor}: context does not exist"),
+            Error::UnknownTypename(actor) => write!(f, "{actor}: typename is not registered"),
+            Error::InvalidConfig { actor, source } => {
+                write!(f, "{actor}: invalid config: {source:#}")
+            }
+            Error::InitFailed { actor, source } => write!(f, "{actor}: init failed: {source:#}"),
+            Error::Dependency {
+                actor,
+                dependency,
+                reason,
+            } => write!(f, "{actor}: dependency on {dependency}: {reason}"),
+            Error::Cycle { context, cycle } => {
+                write!(f, "Cycle detected in context {}: ", context.0)?;
+                for (i, actor) in cycle.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " -> ")?;
+                    }
+                    write!(f, "{}", actor.typename)?;
+                }
+                // typenames alone are ambiguous when an actor type appears more than once
+                write!(f, " (")?;
+                for (i, actor) in cycle[..cycle.len().saturating_sub(1)].iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(
+                        f,
+                        "{}: #{} in scope {}",
+                        actor.typename, actor.index, actor.scope
+                    )?;
+                }
+                write!(f, ")")
+            }
+            Error::ReadConfig { path, source } => {
+                write!(f, "Could not read {}: {source}", path.display())
+            }
+            Error::ParseConfig { path, reason } => {
+                write!(f, "Could not parse {}: {reason}", path.display())
+            }
+            Error::LoadLibrary { path, reason } => {
+                write!(f, "Could not load {}: {reason}", path.display())
+            }
+            Error::Registration {
+                path: Some(path),
+                source,
+            } => write!(f, "Could not register {}: {source:#}", path.display()),
+            Error::Registration { path: None, source } => {
+                write!(f, "Could not register the executable's actors: {source:#}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
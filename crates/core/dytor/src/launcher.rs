@@ -0,0 +1,225 @@
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::{plugins, registry, Config, Error};
+
+#[derive(Deserialize)]
+pub struct LaunchConfig {
+    pub dytor: Config,
+    /// Passed to `dlopen` as is, so relative paths containing a `/` are relative to the working
+    /// directory and bare file names are searched for on the library path
+    #[serde(default)]
+    pub shared_lib_paths: Vec<PathBuf>,
+}
+
+/// Reads a config file, picking the format from its extension: `.yaml`/`.yml`, `.toml` or `.json`
+pub fn load_config(path: impl AsRef<Path>) -> Result<LaunchConfig, Error> {
+    let path = path.as_ref();
+    let parse_error = |reason: String| Error::ParseConfig {
+        path: path.to_owned(),
+        reason,
+    };
+    let text = std::fs::read_to_string(path).map_err(|source| Error::ReadConfig {
+        path: path.to_owned(),
+        source,
+    })?;
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("yaml" | "yml") => serde_yaml::from_str(&text).map_err(|e| parse_error(e.to_string())),
+        Some("toml") => toml::from_str(&text).map_err(|e| parse_error(e.to_string())),
+        Some("json") => serde_json::from_str(&text).map_err(|e| parse_error(e.to_string())),
+        _ => Err(parse_error(
+            "unknown format, expected a .yaml, .yml, .toml or .json file".into(),
+        )),
+    }
+}
+
+/// Loads every shared library in the config, then runs until the system shuts down
+pub fn launch(config: LaunchConfig) -> Result<(), Error> {
+    for path in &config.shared_lib_paths {
+        plugins::load(path)?;
+    }
+    crate::try_run(config.dytor)
+}
+
+/// Checks a config with [`crate::validate`], after loading its libraries
+pub fn check(config: LaunchConfig) -> Result<(), Error> {
+    for path in &config.shared_lib_paths {
+        plugins::load(path)?;
+    }
+    crate::validate(config.dytor)
+}
+
+/// Prints every registered actor and resource, after loading the libraries in `config`
+pub fn list_actors(config: Option<&LaunchConfig>) -> Result<(), Error> {
+    for path in config.iter().flat_map(|c| &c.shared_lib_paths) {
+        plugins::load(path)?;
+    }
+    for actor in registry::actors() {
+        println!("{actor}");
+    }
+    for resource in registry::resources() {
+        println!("resource {resource}");
+    }
+    Ok(())
+}
+
+/// Prints a JSON Schema for launcher config files, after loading the libraries in `config`
+#[cfg(feature = "schema")]
+pub fn print_schema(config: Option<&LaunchConfig>) -> Result<(), Error> {
+    for path in config.iter().flat_map(|c| &c.shared_lib_paths) {
+        plugins::load(path)?;
+    }
+    let mut dytor = crate::schema::config_schema();
+    // refs are resolved against the document root, so the definitions have to move up
+    let defs = dytor.remove("$defs");
+    dytor.remove("$schema");
+    let schema = serde_json::json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "dytor launcher config",
+        "type": "object",
+        "properties": {
+            "dytor": dytor,
+            "shared_lib_paths": { "type": "array", "items": { "type": "string" } },
+        },
+        "required": ["dytor"],
+        "$defs": defs,
+    });
+    println!("{}", serde_json::to_string_pretty(&schema).unwrap());
+    Ok(())
+}
+
+/// Runs the config file named on the command line, exiting the process on failure.
+/// With `--check`, validates the config instead of running it. With `--list-actors`, prints what's
+/// registered instead of running anything. With `--schema`
+/// (and the `schema` feature), prints a JSON Schema for config files.
+pub fn main() {
+    let mut args = std::env::args_os();
+    let program = args.next().unwrap_or_else(|| OsString::from("dytor"));
+    let args: Vec<_> = args.collect();
+    let res = match args.as_slice() {
+        [path] if !path.to_string_lossy().starts_with("--") => load_config(path).and_then(launch),
+        [flag, path] if flag == "--check" => load_config(path).and_then(check),
+        [flag] if flag == "--list-actors" => list_actors(None),
+        [flag, path] if flag == "--list-actors" => {
+            load_config(path).and_then(|config| list_actors(Some(&config)))
+        }
+        #[cfg(feature = "schema")]
+        [flag] if flag == "--schema" => print_schema(None),
+        #[cfg(feature = "schema")]
+        [flag, path] if flag == "--schema" => {
+            load_config(path).and_then(|config| print_schema(Some(&config)))
+        }
+        _ => {
+            let program = Path::new(&program).display();
+            eprintln!("usage: {program} <config.yaml|config.toml|config.json>");
+            eprintln!("       {program} --check <config>");
+            eprintln!("       {program} --list-actors [config]");
+            if cfg!(feature = "schema") {
+                eprintln!("       {program} --schema [config]");
+            }
+            std::process::exit(2);
+        }
+    };
+
+    if let Err(e) = res {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::*;
+
+    /// Writes `text` to a file named `name` in a directory of its own, so tests don't collide
+    fn write(name: &str, text: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("dytor-launcher-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, text).unwrap();
+        path
+    }
+
+    #[test]
+    fn formats() {
+        let files = [
+            (
+                "config.yaml",
+                "shared_lib_paths: [libplugin.so]\n\
+                 dytor:\n  \
+                   contexts: [{id: 1}]\n  \
+                   root:\n    \
+                     actors: [{typename: Greeter, name: greeter, config: hello, context: 1}]\n",
+            ),
+            (
+                "config.toml",
+                "shared_lib_paths = [\"libplugin.so\"]\n\
+                 [[dytor.contexts]]\n\
+                 id = 1\n\
+                 [[dytor.root.actors]]\n\
+                 typename = \"Greeter\"\n\
+                 name = \"greeter\"\n\
+                 config = \"hello\"\n\
+                 context = 1\n",
+            ),
+            (
+                "config.json",
+                r#"{"shared_lib_paths": ["libplugin.so"], "dytor": {"contexts": [{"id": 1}],
+                "root": {"actors": [{"typename": "Greeter", "name": "greeter", "config": "hello",
+                "context": 1}]}}}"#,
+            ),
+        ];
+        for (name, text) in files {
+            let config = load_config(write(name, text)).unwrap();
+            assert_eq!(
+                config.shared_lib_paths,
+                [PathBuf::from("libplugin.so")],
+                "{name}"
+            );
+            assert_eq!(config.dytor.contexts.len(), 1, "{name}");
+            let actor = &config.dytor.root.actors[0];
+            assert_eq!(&*actor.typename, "Greeter", "{name}");
+            assert_eq!(actor.name.as_deref(), Some("greeter"), "{name}");
+            assert_eq!(
+                actor.config,
+                serde_value::Value::String("hello".into()),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let path = write("config.ini", "[dytor]\n");
+        let Err(Error::ParseConfig { reason, .. }) = load_config(&path) else {
+            panic!("an .ini file was parsed");
+        };
+        assert_eq!(
+            reason,
+            "unknown format, expected a .yaml, .yml, .toml or .json file"
+        );
+
+        // the root scope is missing
+        for name in ["invalid.yaml", "invalid.toml", "invalid.json"] {
+            let path = write(name, "");
+            let err = load_config(&path).err().unwrap();
+            assert!(matches!(err, Error::ParseConfig { .. }), "{name}: {err}");
+            assert!(err
+                .to_string()
+                .starts_with(&format!("Could not parse {}: ", path.display())));
+        }
+
+        let path = path.with_file_name("missing.yaml");
+        let err = load_config(&path).err().unwrap();
+        assert!(matches!(err, Error::ReadConfig { .. }), "{err}");
+        assert!(err
+            .to_string()
+            .starts_with(&format!("Could not read {}: ", path.display())));
+    }
+}
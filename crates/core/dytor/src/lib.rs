@@ -1,9 +1,9 @@
-#![feature(ptr_metadata, const_type_id)]
+#![feature(ptr_metadata)]
 #![allow(private_bounds)]
 
 use std::ptr::{DynMetadata, Pointee};
 
-pub use context::{ContextId, InitArgs, MainArgs};
+pub use context::{ActorId, ContextId, Grab, InitArgs, MainArgs};
 
 pub use paste;
 
@@ -12,15 +12,31 @@ mod object;
 pub mod queue;
 pub use object::{actor::Actor, UniquelyNamed};
 mod arena;
+pub mod ask;
 pub mod config;
+pub mod dead_letter;
 pub use config::Config;
 pub mod registry;
 pub(crate) use registry::Registry;
 mod context;
+pub mod error;
+pub use error::Error;
+#[cfg(feature = "launcher")]
+pub mod launcher;
+pub mod message;
+pub mod metrics;
+pub mod plugins;
 mod runtime;
+#[cfg(feature = "schema")]
+pub mod schema;
+pub mod timer;
 
 pub use context::Accessor;
-pub use runtime::run;
+pub use lookup::Dependencies;
+pub use message::Handler;
+pub use runtime::{
+    run, start, try_run, validate, ByNameError, Runtime, RuntimeHandle, ShutdownHandle, SpawnError,
+};
 
 pub(crate) trait Dyn: 'static + Pointee<Metadata = DynMetadata<Self>> {}
 impl<T: ?Sized + 'static + Pointee<Metadata = DynMetadata<T>>> Dyn for T {}
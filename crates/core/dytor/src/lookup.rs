@@ -1,46 +1,261 @@
 use std::{
     any::{type_name, TypeId},
-    collections::HashMap,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     marker::PhantomData,
-    mem,
     ptr::{self, DynMetadata, Pointee},
-    sync::Arc,
+    sync::{Arc, RwLock},
 };
 
-use itertools::Itertools;
+use itertools::{Either, Itertools};
 
 use crate::{
     arena::Offset,
     context::ActorId,
+    message::Handler,
     object::{TraitId, VTable},
-    registry, Accessor, ContextId, InitArgs, MainArgs, Registry,
+    registry,
+    runtime::{self, scope},
+    Accessor, ContextId, InitArgs, MainArgs, Registry,
 };
 
+pub use dytor_proc_macros::Dependencies;
+
 #[derive(Clone)]
 pub(crate) struct ActorData {
     pub(crate) id: ActorId,
     pub(crate) vtable: &'static VTable,
     pub(crate) loc: Loc,
+    pub(crate) scope: ScopeId,
+    pub(crate) name: Option<Arc<str>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct ScopeId(pub(crate) u32);
+
+impl ScopeId {
+    pub(crate) fn as_index(self) -> usize {
+        self.0 as usize
+    }
 }
 
-#[derive(Default)]
+#[derive(Clone)]
+pub(crate) struct ScopeData {
+    pub(crate) name: Option<Arc<str>>,
+    pub(crate) parent: Option<ScopeId>,
+    pub(crate) children: HashMap<Arc<str>, ScopeId>,
+    pub(crate) imports: Vec<ScopeId>,
+    pub(crate) actors: Vec<ActorId>,
+    /// The scope itself, its ancestors (nearest first), then its imports
+    pub(crate) visible: Box<[ScopeId]>,
+}
+
+/// The config's actors and scopes are shared by every copy of the tree, since they're fixed once
+/// the system has started. Actors spawned since are kept apart, and only while they're running.
+#[derive(Clone)]
 pub(crate) struct ActorTree {
-    // TODO: actually make this a tree
-    pub(crate) actors: Vec<ActorData>,
+    /// The config's actors, indexed by `ActorId::as_index`
+    pub(crate) actors: Arc<[ActorData]>,
+    /// Indexed by `ScopeId::as_index`; the root scope comes first. Only the config's actors are
+    /// listed in their scopes.
+    pub(crate) scopes: Arc<[ScopeData]>,
+    /// The config's actors by location
+    pub(crate) config_at: Arc<HashMap<Loc, ActorId>>,
+    /// The config's actors stopped with [`MainArgs::stop`], which lookups no longer find
+    pub(crate) removed: HashSet<ActorId>,
+    /// Spawned actors that are still running
+    pub(crate) spawned: BTreeMap<ActorId, ActorData>,
+    /// The running spawned actors declared in each scope
+    pub(crate) spawned_in: HashMap<ScopeId, BTreeSet<ActorId>>,
+    pub(crate) spawned_at: HashMap<Loc, ActorId>,
+    /// The last spawned actor to stop in each slot of an arena, by [`Loc::slot`], so dead letters
+    /// to it can still be reported until the next actor with the slot stops too
+    pub(crate) last_stopped: HashMap<Loc, ActorData>,
+    /// The id the next spawned actor gets
+    pub(crate) next_id: u32,
+}
+
+impl ActorTree {
+    /// `actors` must be sorted by id
+    pub(crate) fn new(actors: Vec<ActorData>, scopes: Vec<ScopeData>) -> Self {
+        Self {
+            config_at: Arc::new(actors.iter().map(|actor| (actor.loc, actor.id)).collect()),
+            next_id: actors.len() as u32 + 1,
+            actors: actors.into(),
+            scopes: scopes.into(),
+            removed: HashSet::new(),
+            spawned: BTreeMap::new(),
+            spawned_in: HashMap::new(),
+            spawned_at: HashMap::new(),
+            last_stopped: HashMap::new(),
+        }
+    }
+
+    /// Panics if `id` is a spawned actor that has stopped
+    pub(crate) fn actor(&self, id: ActorId) -> &ActorData {
+        self.actors
+            .get(id.as_index())
+            .unwrap_or_else(|| &self.spawned[&id])
+    }
+
+    /// Position of the actor among those declared in its scope: the config's actors in the order
+    /// they're listed, then the spawned actors still running, in the order they were spawned
+    pub(crate) fn index_in_scope(&self, id: ActorId) -> usize {
+        let scope = self.actor(id).scope;
+        let config = &self.scopes[scope.as_index()].actors;
+        config
+            .iter()
+            .position(|&actor| actor == id)
+            .unwrap_or_else(|| config.len() + self.spawned_in[&scope].range(..id).count())
+    }
+
+    /// `None` if `loc` is a spawned actor that has stopped, unless it's the last one in its slot
+    pub(crate) fn actor_at(&self, loc: Loc) -> Option<&ActorData> {
+        let id = self
+            .config_at
+            .get(&loc)
+            .or_else(|| self.spawned_at.get(&loc));
+        if let Some(&id) = id {
+            return Some(self.actor(id));
+        }
+        self.last_stopped
+            .get(&loc.slot())
+            .filter(|actor| actor.loc == loc)
+    }
+
+    /// Every actor that queries made by `from_actor` can resolve to. Every actor that hasn't been
+    /// removed is visible from outside the system, as `None`.
+    pub(crate) fn visible(
+        &self,
+        from_actor: Option<ActorId>,
+    ) -> impl '_ + Iterator<Item = &ActorData> {
+        let Some(from_actor) = from_actor else {
+            let config = self.actors.iter();
+            return Either::Left(
+                config
+                    .filter(|actor| !self.removed.contains(&actor.id))
+                    .chain(self.spawned.values()),
+            );
+        };
+        let scope = self.actor(from_actor).scope;
+        Either::Right(
+            self.scopes[scope.as_index()]
+                .visible
+                .iter()
+                .flat_map(|s| {
+                    let config = self.scopes[s.as_index()].actors.iter();
+                    let config = config.filter(|id| !self.removed.contains(id));
+                    config.chain(self.spawned_in.get(s).into_iter().flatten())
+                })
+                .map(|&id| self.actor(id)),
+        )
+    }
+
+    /// Whether `actor` passes `filter`, with scope paths resolved from `from_actor`'s scope, or
+    /// the root scope from outside the system
+    pub(crate) fn matches(
+        &self,
+        from_actor: Option<ActorId>,
+        actor: ActorId,
+        filter: &Filter,
+    ) -> bool {
+        let actor = self.actor(actor);
+        if let Some(name) = &filter.name {
+            if actor.name.as_deref() != Some(&**name) {
+                return false;
+            }
+        }
+        if let Some(path) = &filter.scope {
+            let from = from_actor.map_or(ScopeId(0), |from| self.actor(from).scope);
+            if scope::resolve(&self.scopes, from, path) != Some(actor.scope) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// The tree every context shares, which actors spawned after startup are added to. Readers take a
+/// snapshot, which later spawns leave as it was. A spawn or stop only copies the tree if a snapshot
+/// is still held, and then only the part for actors spawned since startup; see [`ActorTree`].
+pub(crate) struct LiveTree(RwLock<Arc<ActorTree>>);
+
+impl LiveTree {
+    pub(crate) fn new(tree: ActorTree) -> Self {
+        Self(RwLock::new(Arc::new(tree)))
+    }
+
+    pub(crate) fn snapshot(&self) -> Arc<ActorTree> {
+        self.0.read().unwrap().clone()
+    }
+
+    /// Adds an unnamed actor to `scope`, giving it the next id
+    pub(crate) fn insert(&self, vtable: &'static VTable, loc: Loc, scope: ScopeId) -> ActorId {
+        let mut tree = self.0.write().unwrap();
+        let tree = Arc::make_mut(&mut tree);
+        let id = ActorId::new(tree.next_id).unwrap();
+        tree.next_id += 1;
+        tree.spawned.insert(
+            id,
+            ActorData {
+                id,
+                vtable,
+                loc,
+                scope,
+                name: None,
+            },
+        );
+        tree.spawned_in.entry(scope).or_default().insert(id);
+        tree.spawned_at.insert(loc, id);
+        id
+    }
+
+    /// Takes the actor at `loc` out of the tree, so later lookups don't find it. Its id stays
+    /// taken.
+    pub(crate) fn remove(&self, loc: Loc) {
+        let mut tree = self.0.write().unwrap();
+        let tree = Arc::make_mut(&mut tree);
+        if let Some(&id) = tree.config_at.get(&loc) {
+            tree.removed.insert(id);
+            return;
+        }
+        let id = tree.spawned_at.remove(&loc).unwrap();
+        let actor = tree.spawned.remove(&id).unwrap();
+        let in_scope = tree.spawned_in.get_mut(&actor.scope).unwrap();
+        in_scope.remove(&id);
+        if in_scope.is_empty() {
+            tree.spawned_in.remove(&actor.scope);
+        }
+        tree.last_stopped.insert(loc.slot(), actor);
+    }
+}
+
+/// Narrows down a lookup to actors with a given instance name and/or declared directly in a given
+/// scope. Scope paths are resolved the same way as `imported_scopes`; the scope still has to be
+/// visible to the actor making the lookup.
+#[derive(Clone, Default, Debug)]
+pub(crate) struct Filter {
+    pub(crate) name: Option<Arc<str>>,
+    pub(crate) scope: Option<Arc<str>>,
 }
 
 pub(crate) trait Lookup<T: ?Sized, D> {
-    fn lookup(&self, from_actor: ActorId) -> impl '_ + Iterator<Item = (ActorId, Key<T>)>;
+    /// The metadata to view an actor of this type as a `T` with, if it is one
+    fn meta(vtable: &VTable) -> Option<<T as Pointee>::Metadata>;
+
+    fn lookup(&self, from_actor: Option<ActorId>) -> impl '_ + Iterator<Item = (ActorId, Key<T>)>;
 }
 
 impl<T: 'static> Lookup<T, ()> for ActorTree
 where
     T: Pointee<Metadata = ()>,
 {
-    fn lookup(&self, _from_actor: ActorId) -> impl '_ + Iterator<Item = (ActorId, Key<T>)> {
+    fn meta(vtable: &VTable) -> Option<()> {
+        (vtable.type_id == TypeId::of::<T>()).then_some(())
+    }
+
+    fn lookup(&self, from_actor: Option<ActorId>) -> impl '_ + Iterator<Item = (ActorId, Key<T>)> {
         let type_id = TypeId::of::<T>();
-        self.actors
-            .iter()
+        self.visible(from_actor)
             .filter(move |actor| actor.vtable.type_id == type_id)
             .map(|actor| {
                 (
@@ -58,14 +273,23 @@ impl<T: ?Sized + 'static> Lookup<T, DynMetadata<T>> for ActorTree
 where
     T: Pointee<Metadata = DynMetadata<T>>,
 {
-    fn lookup(&self, _from_actor: ActorId) -> impl '_ + Iterator<Item = (ActorId, Key<T>)> {
+    fn meta(vtable: &VTable) -> Option<DynMetadata<T>> {
+        let types = Registry::get().trait_types.get(&TraitId::of::<T>())?;
+        let t = types.iter().find(|x| x.type_id == vtable.type_id)?;
+        Some(unsafe {
+            std::mem::transmute::<registry::DynMetaPlaceholder, std::ptr::DynMetadata<T>>(
+                t.dyn_meta,
+            )
+        })
+    }
+
+    fn lookup(&self, from_actor: Option<ActorId>) -> impl '_ + Iterator<Item = (ActorId, Key<T>)> {
         let trait_id = TraitId::of::<T>();
         let types: &[_] = Registry::get()
             .trait_types
             .get(&trait_id)
             .map_or(&[], AsRef::as_ref);
-        self.actors
-            .iter()
+        self.visible(from_actor)
             .filter_map(|actor| {
                 Some((
                     actor,
@@ -89,6 +313,133 @@ where
     }
 }
 
+/// A lookup an actor makes during `init`, declared up front through [`crate::Actor::dependencies`]
+pub struct Dependency {
+    pub(crate) type_name: fn() -> &'static str,
+    pub(crate) kind: DependencyKind,
+    pub(crate) lookup: fn(&ActorTree, ActorId) -> Vec<(ActorId, ContextId)>,
+    pub(crate) filter: Filter,
+}
+
+/// Lookups made by a struct deriving [`Dependencies`], which fills in its fields with [`crate::Grab`].
+///
+/// Supported field types are `Key<T>`, `Option<Key<T>>`, `Vec<Key<T>>`, `Accessor<T>`,
+/// `Vec<Accessor<T>>`, `BroadcastGroup<T>` and `AcyclicLocalKey<T>`. A field can be narrowed down
+/// with `#[dytor(name = "...")]` and `#[dytor(scope = "...")]`, and the path to this crate can be
+/// set with `#[dytor(crate = "...")]` on the struct.
+///
+/// If an actor derives this itself, `register_actor!` declares its lookups as
+/// [`crate::Actor::dependencies`] as well, so `init` only has to [`crate::Grab`] itself.
+///
+/// Any other field type is rejected:
+///
+/// ```compile_fail
+/// #[derive(dytor::Dependencies)]
+/// struct Lookups {
+///     count: u32,
+/// }
+/// ```
+pub trait Dependencies {
+    fn list() -> Vec<Dependency>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DependencyKind {
+    /// [`Query::exactly_one_key`] or [`Query::exactly_one_accessor`]
+    ExactlyOne,
+    /// [`Query::optional_key`]
+    AtMostOne,
+    /// [`Query::acyclic_local_key`]
+    AcyclicLocal,
+    /// [`Query::all_keys`], [`Query::all_accessors`] or [`Query::broadcast_group`]
+    Any,
+}
+
+fn lookup_ids<T: ?Sized + 'static>(tree: &ActorTree, from: ActorId) -> Vec<(ActorId, ContextId)>
+where
+    ActorTree: Lookup<T, <T as Pointee>::Metadata>,
+{
+    <ActorTree as Lookup<T, _>>::lookup(tree, Some(from))
+        .map(|(id, key)| (id, key.loc.context_id))
+        .collect()
+}
+
+impl Dependency {
+    pub fn new<T: ?Sized + 'static>(kind: DependencyKind) -> Self
+    where
+        ActorTree: Lookup<T, <T as Pointee>::Metadata>,
+    {
+        Self {
+            type_name: type_name::<T>,
+            kind,
+            lookup: lookup_ids::<T>,
+            filter: Filter::default(),
+        }
+    }
+
+    pub fn at_most_one<T: ?Sized + 'static>() -> Self
+    where
+        ActorTree: Lookup<T, <T as Pointee>::Metadata>,
+    {
+        Self::new::<T>(DependencyKind::AtMostOne)
+    }
+
+    pub fn exactly_one<T: ?Sized + 'static>() -> Self
+    where
+        ActorTree: Lookup<T, <T as Pointee>::Metadata>,
+    {
+        Self::new::<T>(DependencyKind::ExactlyOne)
+    }
+
+    pub fn acyclic_local<T: ?Sized + 'static>() -> Self
+    where
+        ActorTree: Lookup<T, <T as Pointee>::Metadata>,
+    {
+        Self::new::<T>(DependencyKind::AcyclicLocal)
+    }
+
+    pub fn any<T: ?Sized + 'static>() -> Self
+    where
+        ActorTree: Lookup<T, <T as Pointee>::Metadata>,
+    {
+        Self::new::<T>(DependencyKind::Any)
+    }
+
+    /// See [`Query::named`]
+    pub fn named(mut self, name: impl Into<Arc<str>>) -> Self {
+        self.filter.name = Some(name.into());
+        self
+    }
+
+    /// See [`Query::in_scope`]
+    pub fn in_scope(mut self, path: impl Into<Arc<str>>) -> Self {
+        self.filter.scope = Some(path.into());
+        self
+    }
+
+    pub fn kind(&self) -> DependencyKind {
+        self.kind
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        (self.type_name)()
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.filter.name.as_deref()
+    }
+
+    pub fn scope(&self) -> Option<&str> {
+        self.filter.scope.as_deref()
+    }
+
+    pub(crate) fn resolve(&self, tree: &ActorTree, from: ActorId) -> Vec<(ActorId, ContextId)> {
+        let mut found = (self.lookup)(tree, from);
+        found.retain(|&(id, _)| tree.matches(Some(from), id, &self.filter));
+        found
+    }
+}
+
 pub(crate) struct DependenceRelation {
     pub(crate) from: ActorId,
     pub(crate) to: ActorId,
@@ -96,56 +447,73 @@ pub(crate) struct DependenceRelation {
 
 pub struct Query<'a, 'b, T: ?Sized, ActorT> {
     pub(crate) init_args: &'a mut InitArgs<'b, ActorT>,
+    pub(crate) filter: Filter,
     pub(crate) phantom: PhantomData<fn() -> T>,
 }
 
+impl<T: ?Sized, ActorT> Query<'_, '_, T, ActorT> {
+    /// Only match actors whose config gives them this instance name
+    pub fn named(mut self, name: impl Into<Arc<str>>) -> Self {
+        self.filter.name = Some(name.into());
+        self
+    }
+
+    /// Only match actors declared directly in the scope at `path`, which is resolved like an entry
+    /// of `imported_scopes`
+    pub fn in_scope(mut self, path: impl Into<Arc<str>>) -> Self {
+        self.filter.scope = Some(path.into());
+        self
+    }
+}
+
 impl<T: 'static + ?Sized, ActorT> Query<'_, '_, T, ActorT>
 where
     ActorTree: Lookup<T, <T as Pointee>::Metadata>,
 {
+    fn lookup(&self) -> impl '_ + Iterator<Item = (ActorId, Key<T>)> {
+        let tree = &*self.init_args.data.tree;
+        let from = Some(self.init_args.actor_being_constructed);
+        let filter = &self.filter;
+        tree.lookup(from)
+            .filter(move |&(id, _)| tree.matches(from, id, filter))
+    }
+
     pub fn all_keys(&mut self) -> impl '_ + Iterator<Item = Key<T>> {
-        self.init_args
-            .data
-            .tree
-            .lookup(self.init_args.actor_being_constructed)
-            .map(|(_, b)| b)
+        self.lookup().map(|(_, b)| b)
     }
 
     pub fn exactly_one_key(&mut self) -> Key<T> {
-        self.init_args
-            .data
-            .tree
-            .lookup(self.init_args.actor_being_constructed)
+        self.lookup()
             .exactly_one()
             .map(|(_, key)| key)
             .unwrap_or_else(|_| panic!())
     }
 
-    pub fn all_accessors(&mut self) -> impl '_ + Iterator<Item = Accessor<T>> {
+    /// Panics if more than one actor matches
+    pub fn optional_key(&mut self) -> Option<Key<T>> {
+        self.lookup()
+            .at_most_one()
+            .map(|found| found.map(|(_, key)| key))
+            .unwrap_or_else(|_| panic!())
+    }
+
+    pub fn exactly_one_accessor(&mut self) -> Accessor<T> {
+        let key = self.exactly_one_key();
         self.init_args
             .data
-            .tree
-            .lookup(self.init_args.actor_being_constructed)
-            .map(|(_, key)| {
-                mem::forget(self.init_args.control_block_ptr.clone());
-                Accessor {
-                    offset: key.loc.offset,
-                    metadata: key.meta,
-                    ctx_queue: (self.init_args.data.make_tx[key.loc.context_id.as_index()])(),
-                    control_block_ptr: self.init_args.control_block_ptr.0,
-                    _phantom: PhantomData,
-                }
-            })
+            .accessor_for_key(self.init_args.control_block_ptr, key)
+    }
+
+    pub fn all_accessors(&mut self) -> impl '_ + Iterator<Item = Accessor<T>> {
+        let data = &*self.init_args.data;
+        let control_block_ptr = self.init_args.control_block_ptr;
+        self.lookup()
+            .map(move |(_, key)| data.accessor_for_key(control_block_ptr, key))
     }
 
     pub fn broadcast_group(self) -> BroadcastGroup<T> {
         let mut map: HashMap<_, Vec<_>> = HashMap::new();
-        for (_, key) in self
-            .init_args
-            .data
-            .tree
-            .lookup(self.init_args.actor_being_constructed)
-        {
+        for (_, key) in self.lookup() {
             map.entry(key.loc.context_id)
                 .or_default()
                 .push((key.loc.offset, key.meta));
@@ -160,12 +528,11 @@ where
         BroadcastGroup { by_context }
     }
 
+    /// Constructs the one matching actor, which must be on this context, before returning its
+    /// key. If it isn't constructed, because its `init` failed, it's part of a cycle or it has
+    /// stopped, the actor asking is dropped once its `init` returns instead of starting.
     pub fn acyclic_local_key(&mut self) -> AcyclicLocalKey<T> {
-        let mut it = self
-            .init_args
-            .data
-            .tree
-            .lookup(self.init_args.actor_being_constructed);
+        let mut it = self.lookup();
         let (local_actor_id, local_actor_key) = it.next().unwrap();
         assert!(it.next().is_none());
         assert_eq!(local_actor_key.loc.context_id, self.init_args.data.data.id);
@@ -181,6 +548,16 @@ where
                 from,
                 to: local_actor_id,
             });
+        runtime::construct_actor(
+            self.init_args.data,
+            local_actor_id,
+            self.init_args.control_block_ptr,
+            self.init_args.resources,
+        );
+        let data = &*self.init_args.data;
+        if data.is_stopped(&data.arena, local_actor_key.loc.offset) {
+            self.init_args.data.dependency_missing = true;
+        }
 
         AcyclicLocalKey {
             offset: local_actor_key.loc.offset,
@@ -199,12 +576,25 @@ where
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
 pub(crate) struct Loc {
     pub(crate) context_id: ContextId,
     pub(crate) offset: Offset,
 }
 
+impl Loc {
+    /// The same for every actor that has had this one's slot in its context's arena
+    fn slot(self) -> Loc {
+        Loc {
+            offset: Offset {
+                generation: 0,
+                ..self.offset
+            },
+            ..self
+        }
+    }
+}
+
 type MetaSlice<T> = Arc<[(Offset, <T as Pointee>::Metadata)]>;
 
 pub struct BroadcastGroup<T: ?Sized> {
@@ -224,6 +614,17 @@ impl<T: ?Sized> Clone for Key<T> {
 
 impl<T: ?Sized> Copy for Key<T> {}
 
+impl<T: ?Sized> Key<T> {
+    /// Same as [`MainArgs::tell`]
+    pub fn tell<M: 'static + Send>(self, args: &mut MainArgs, msg: M)
+    where
+        T: Handler<M>,
+        <T as Pointee>::Metadata: 'static,
+    {
+        args.tell(self, msg)
+    }
+}
+
 impl<T: ?Sized> std::fmt::Debug for Key<T> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let name = type_name::<T>();
@@ -247,21 +648,277 @@ where
     }
 }
 
+/// A supervised actor may have been dropped after a panic, see [`crate::config::Supervision`]
+fn assert_alive(args: &MainArgs, offset: Offset) {
+    assert!(
+        !args.context_data.is_stopped(args.arena, offset),
+        "local dependency has stopped after a panic"
+    );
+}
+
 impl<T: ?Sized> AcyclicLocalKey<T> {
     /// This has to take &mut self since we can 'launder' the MainArgs borrow with call()
+    ///
+    /// A panic while the reference is in use is blamed on the caller, not the dependency; see
+    /// [`Self::call`].
     pub fn borrow_mut(&mut self, args: &mut MainArgs) -> &mut T {
+        assert_alive(args, self.offset);
         let ptr: *mut T = ptr::from_raw_parts_mut(args.arena.offset(self.offset) as _, self.meta);
         unsafe { &mut *ptr }
     }
 
     /// f can't be FnMut or FnOnce so that people won't capture mutable refs to other AcyclicLocalKeys,
     /// which has the potential to break aliasing rules in cases like the diamond pattern
+    ///
+    /// While `f` runs, the dependency counts as the actor being called: a panic in `f` is blamed
+    /// on it, so its [`crate::config::Supervision`] applies rather than the caller's, and
+    /// messages sent from `f` come from it.
+    ///
+    /// The result may borrow from the dependency, but not from `args`, which the caller gets back
+    /// as soon as this returns.
     pub fn call<'a, 'b, R: 'a>(
         &'a mut self,
-        args: &'a mut MainArgs,
-        f: impl Fn(&'a mut MainArgs, &'a mut T) -> R,
+        args: &mut MainArgs,
+        f: impl Fn(&mut MainArgs, &'a mut T) -> R,
     ) -> R {
+        assert_alive(args, self.offset);
         let ptr: *mut T = ptr::from_raw_parts_mut(args.arena.offset(self.offset) as _, self.meta);
-        f(args, unsafe { &mut *ptr })
+        // left pointing at the dependency if `f` panics
+        let caller = args.context_data.current_actor.replace(self.offset);
+        let res = f(args, unsafe { &mut *ptr });
+        args.context_data.current_actor = caller;
+        res
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Mutex, thread, time::Duration};
+
+    use super::*;
+    use crate::{
+        config::{ActorConfig, Scope},
+        register_actor,
+        runtime::{test::config, Runtime},
+        Actor, Dependencies, Grab, Handler, UniquelyNamed,
+    };
+
+    /// Which field of [`Lookups`] reached which peer, by the peer's instance name
+    static REACHED: Mutex<Vec<(&str, String)>> = Mutex::new(Vec::new());
+
+    struct Peer {
+        name: String,
+    }
+
+    impl UniquelyNamed for Peer {
+        fn name() -> &'static str {
+            "Peer"
+        }
+    }
+
+    register_actor!(Peer);
+
+    struct Reach(&'static str);
+
+    impl Handler<Reach> for Peer {
+        fn handle(&mut self, _args: &mut MainArgs, msg: Reach) {
+            self.reach(msg.0);
+        }
+    }
+
+    impl Peer {
+        fn reach(&self, field: &'static str) {
+            REACHED.lock().unwrap().push((field, self.name.clone()));
+        }
+    }
+
+    impl Actor for Peer {
+        type Config = String;
+
+        fn init(_args: InitArgs<Self>, name: String) -> anyhow::Result<Self> {
+            Ok(Self { name })
+        }
+    }
+
+    #[derive(Dependencies)]
+    #[dytor(crate = "crate")]
+    struct Lookups {
+        #[dytor(name = "b")]
+        named: Key<Peer>,
+        #[dytor(name = "missing")]
+        missing: Option<Key<Peer>>,
+        all: Vec<Key<Peer>>,
+        #[dytor(name = "a")]
+        accessor: Accessor<Peer>,
+        #[dytor(scope = "inner")]
+        accessors: Vec<Accessor<Peer>>,
+        #[dytor(scope = "inner")]
+        group: BroadcastGroup<Peer>,
+        #[dytor(name = "a")]
+        local: AcyclicLocalKey<Peer>,
+    }
+
+    impl UniquelyNamed for Lookups {
+        fn name() -> &'static str {
+            "Lookups"
+        }
+    }
+
+    register_actor!(Lookups);
+
+    impl Actor for Lookups {
+        type Config = ();
+
+        fn init(mut args: InitArgs<Self>, _config: ()) -> anyhow::Result<Self> {
+            Ok(args.grab())
+        }
+
+        fn on_start(&mut self, args: &mut MainArgs) {
+            assert!(self.missing.is_none());
+            args.tell(self.named, Reach("named"));
+            for &key in &self.all {
+                args.tell(key, Reach("all"));
+            }
+            self.accessor.tell(Reach("accessor")).unwrap();
+            for accessor in &self.accessors {
+                accessor.tell(Reach("accessors")).unwrap();
+            }
+            args.broadcast(&self.group, |_, peer| peer.reach("group"));
+            self.local.call(args, |_, peer| peer.reach("local"));
+        }
+    }
+
+    /// Looks peers up with [`Query`] directly rather than through a derive
+    struct Finder;
+
+    impl UniquelyNamed for Finder {
+        fn name() -> &'static str {
+            "Finder"
+        }
+    }
+
+    register_actor!(Finder);
+
+    impl Actor for Finder {
+        type Config = ();
+
+        fn init(mut args: InitArgs<Self>, _config: ()) -> anyhow::Result<Self> {
+            let named = args.query::<Peer>().named("b").exactly_one_key();
+            args.tell(named, Reach("name"));
+            // peers `a` and `b` are visible, but not declared in `inner`
+            let in_scope: Vec<_> = args.query::<Peer>().in_scope("inner").all_keys().collect();
+            for key in in_scope {
+                args.tell(key, Reach("scope"));
+            }
+            let both = args
+                .query::<Peer>()
+                .named("c")
+                .in_scope("inner")
+                .exactly_one_key();
+            args.tell(both, Reach("name and scope"));
+
+            let none = |mut query: Query<Peer, Self>| query.optional_key().is_none();
+            assert!(none(args.query().named("a").in_scope("inner")));
+            assert!(none(args.query().named("missing")));
+            assert!(none(args.query().in_scope("missing")));
+            Ok(Self)
+        }
+    }
+
+    fn named(mut actor: ActorConfig, name: &str) -> ActorConfig {
+        actor.name = Some(name.into());
+        actor.config = serde_value::Value::String(name.into());
+        actor
+    }
+
+    /// Peers `a` and `b` in the root scope, which imports `inner` with peer `c`, and `actor` on
+    /// context 1 with `a`
+    fn peers_config(actor: &str) -> crate::Config {
+        let mut config = config(&[("Peer", 1), ("Peer", 2), (actor, 1), ("Peer", 2)]);
+        let mut actors = config.root.actors.drain(..);
+        let (a, b, actor, c) = (
+            actors.next().unwrap(),
+            actors.next().unwrap(),
+            actors.next().unwrap(),
+            actors.next().unwrap(),
+        );
+        drop(actors);
+        config.root.actors = vec![named(a, "a"), named(b, "b"), actor];
+        let inner = Scope {
+            name: None,
+            children: Default::default(),
+            actors: vec![named(c, "c")],
+            imported_scopes: Vec::new(),
+        };
+        config.root.children.insert("inner".into(), inner);
+        config.root.imported_scopes.push("inner".into());
+        config
+    }
+
+    /// Runs until `fields` have reached `count` peers between them, returning what they reached
+    fn run_until_reached(
+        config: crate::Config,
+        fields: &[&str],
+        count: usize,
+    ) -> Vec<(&'static str, String)> {
+        let reached_by_fields = || {
+            let mut reached = REACHED.lock().unwrap().clone();
+            reached.retain(|(field, _)| fields.contains(field));
+            reached.sort();
+            reached
+        };
+        let handle = Runtime::start(config).unwrap();
+        while reached_by_fields().len() < count {
+            thread::sleep(Duration::from_millis(1));
+        }
+        handle.shutdown();
+        handle.join();
+        reached_by_fields()
+    }
+
+    #[test]
+    fn derived_dependencies() {
+        let declared = Lookups::list();
+        let declared: Vec<_> = declared
+            .iter()
+            .map(|d| (d.kind(), d.name(), d.scope()))
+            .collect();
+        assert_eq!(
+            declared,
+            [
+                (DependencyKind::ExactlyOne, Some("b"), None),
+                (DependencyKind::AtMostOne, Some("missing"), None),
+                (DependencyKind::Any, None, None),
+                (DependencyKind::ExactlyOne, Some("a"), None),
+                (DependencyKind::Any, None, Some("inner")),
+                (DependencyKind::Any, None, Some("inner")),
+                (DependencyKind::AcyclicLocal, Some("a"), None),
+            ]
+        );
+        // declared through `register_actor!` without `Actor::dependencies` being overridden
+        let vtable = Registry::get().actor_types[&TypeId::of::<Lookups>()];
+        assert_eq!((vtable.dependencies)().len(), declared.len());
+
+        let fields = ["named", "all", "accessor", "accessors", "group", "local"];
+        let reached = run_until_reached(peers_config("Lookups"), &fields, 8);
+        let expected = [
+            ("accessor", "a"),
+            ("accessors", "c"),
+            ("all", "a"),
+            ("all", "b"),
+            ("all", "c"),
+            ("group", "c"),
+            ("local", "a"),
+            ("named", "b"),
+        ];
+        assert_eq!(reached, expected.map(|(f, p)| (f, p.to_string())));
+    }
+
+    #[test]
+    fn query_filters() {
+        let fields = ["name", "scope", "name and scope"];
+        let reached = run_until_reached(peers_config("Finder"), &fields, 3);
+        let expected = [("name", "b"), ("name and scope", "c"), ("scope", "c")];
+        assert_eq!(reached, expected.map(|(f, p)| (f, p.to_string())));
     }
 }
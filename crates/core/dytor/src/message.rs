@@ -0,0 +1,155 @@
+//! Typed messages, an alternative to sending closures. An actor that implements [`Handler<M>`]
+//! can be sent an `M` with `tell`, on [`crate::MainArgs`], [`crate::InitArgs`],
+//! [`crate::lookup::Key`] or [`crate::Accessor`].
+//!
+//! A typed message of up to 48 bytes, aligned to at most a word, is stored inline in the queue, so
+//! sending one doesn't allocate; a larger one is boxed. In the queue it's wrapped in a call to
+//! [`Handler::handle`] just like a closure, so the runtime can't inspect it; only the handler sees
+//! the message itself. Closures are stored inline too if their captures fit, and boxed otherwise.
+//! Typed and closure messages share the ordering guarantees described in [`crate::queue`].
+
+use std::{
+    mem::{self, ManuallyDrop, MaybeUninit},
+    ptr::{self, Pointee},
+};
+
+use crate::{arena::Offset, context::Context, lookup::Loc, MainArgs};
+
+/// Handles messages of type `M`, usually an enum of everything the actor can be told.
+///
+/// A trait object can be told `M` too if its trait has `Handler<M>` as a supertrait, in which
+/// case the message is dispatched through the trait's vtable.
+///
+/// Sending an `M` larger than 48 bytes allocates, since it's boxed rather than stored inline in
+/// the queue; box its larger fields, or send a `Box<M>`, to keep that visible.
+pub trait Handler<M> {
+    fn handle(&mut self, args: &mut MainArgs, msg: M);
+}
+
+/// Room for a closure's captures, or a typed message with its target's offset and metadata. The
+/// 48 bytes documented for typed messages leave a word each for the offset and the metadata.
+const INLINE_WORDS: usize = 8;
+
+/// A message as it sits in a queue: a closure run on the receiving context, stored inline if its
+/// captures fit and boxed otherwise
+pub(crate) struct Msg {
+    call: unsafe fn(*mut u8, &mut Context),
+    drop: unsafe fn(*mut u8),
+    pub(crate) origin: Origin,
+    payload: [MaybeUninit<usize>; INLINE_WORDS],
+}
+
+/// Who sent a message, kept in case it turns out to be a [`crate::dead_letter::DeadLetter`]
+#[derive(Clone, Copy)]
+pub(crate) enum Origin {
+    Actor(Loc),
+    Accessor,
+    Timer,
+}
+
+// safety: only constructed from `Send` closures
+unsafe impl Send for Msg {}
+
+impl Msg {
+    pub(crate) fn new<F: 'static + Send + FnOnce(&mut Context)>(f: F) -> Self {
+        if fits_inline::<F>() {
+            unsafe { Self::new_unchecked(f) }
+        } else {
+            let f = Box::new(f);
+            unsafe { Self::new_unchecked(move |ctx: &mut Context| f(ctx)) }
+        }
+    }
+
+    /// Safety: `f` must fit inline
+    unsafe fn new_unchecked<F: 'static + Send + FnOnce(&mut Context)>(f: F) -> Self {
+        let mut payload = [MaybeUninit::uninit(); INLINE_WORDS];
+        unsafe { ptr::write(payload.as_mut_ptr().cast(), f) };
+        Self {
+            call: call::<F>,
+            drop: drop_payload::<F>,
+            origin: Origin::Accessor,
+            payload,
+        }
+    }
+
+    pub(crate) fn run(self, ctx: &mut Context) {
+        let mut this = ManuallyDrop::new(self);
+        ctx.data.origin = this.origin;
+        unsafe { (this.call)(this.payload.as_mut_ptr().cast(), ctx) }
+    }
+
+    /// A typed message to the actor at `offset`, stored inline unless it's larger than 48 bytes
+    pub(crate) fn tell<T: ?Sized + Handler<M>, M: 'static + Send>(
+        offset: Offset,
+        meta: <T as Pointee>::Metadata,
+        msg: M,
+    ) -> Self
+    where
+        <T as Pointee>::Metadata: 'static,
+    {
+        Self::new(move |ctx: &mut Context| {
+            ctx.call_actor(offset, meta, |args, actor: &mut T| actor.handle(args, msg))
+        })
+    }
+}
+
+const fn fits_inline<F>() -> bool {
+    mem::size_of::<F>() <= mem::size_of::<[usize; INLINE_WORDS]>()
+        && mem::align_of::<F>() <= mem::align_of::<usize>()
+}
+
+impl Drop for Msg {
+    fn drop(&mut self) {
+        unsafe { (self.drop)(self.payload.as_mut_ptr().cast()) }
+    }
+}
+
+unsafe fn call<F: FnOnce(&mut Context)>(payload: *mut u8, ctx: &mut Context) {
+    let f = unsafe { ptr::read(payload as *mut F) };
+    f(ctx)
+}
+
+unsafe fn drop_payload<F>(payload: *mut u8) {
+    unsafe { ptr::drop_in_place(payload as *mut F) }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+
+    #[test]
+    fn unrun_messages_dropped() {
+        static DROPPED: AtomicU32 = AtomicU32::new(0);
+        struct Counted(#[allow(unused)] [u8; 16]);
+        impl Drop for Counted {
+            fn drop(&mut self) {
+                DROPPED.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        struct Target;
+        impl Handler<Counted> for Target {
+            fn handle(&mut self, _args: &mut MainArgs, _msg: Counted) {}
+        }
+        impl Handler<(Counted, [u8; 48])> for Target {
+            fn handle(&mut self, _args: &mut MainArgs, _msg: (Counted, [u8; 48])) {}
+        }
+
+        let typed = Msg::tell::<Target, _>(Offset::new(0), (), Counted([0; 16]));
+        // too large to be stored inline, so it's boxed like a closure would be
+        let large_typed = Msg::tell::<Target, _>(Offset::new(0), (), (Counted([0; 16]), [0u8; 48]));
+        let counted = Counted([0; 16]);
+        let inline = Msg::new(move |_| drop(counted));
+        // too large to be stored inline, so it's boxed
+        let counted = Counted([0; 16]);
+        let large = [0u8; 256];
+        let boxed = Msg::new(move |_| drop((counted, large)));
+        assert!(!fits_inline::<([u8; 256], Counted)>());
+
+        assert_eq!(DROPPED.load(Ordering::Relaxed), 0);
+        drop((typed, large_typed, inline, boxed));
+        assert_eq!(DROPPED.load(Ordering::Relaxed), 4);
+    }
+}
@@ -0,0 +1,37 @@
+//! Counters kept by the runtime as it runs
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::config::Backpressure;
+
+/// Messages a context's queue has turned away under its [`Backpressure`] policy
+#[derive(Default, Debug)]
+pub struct QueueMetrics {
+    dropped_newest: AtomicU64,
+    dropped_oldest: AtomicU64,
+    rejected: AtomicU64,
+}
+
+impl QueueMetrics {
+    pub fn dropped_newest(&self) -> u64 {
+        self.dropped_newest.load(Ordering::Relaxed)
+    }
+
+    pub fn dropped_oldest(&self) -> u64 {
+        self.dropped_oldest.load(Ordering::Relaxed)
+    }
+
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn record_dropped(&self, policy: Backpressure, count: u64) {
+        let counter = match policy {
+            Backpressure::Block => return,
+            Backpressure::DropNewest => &self.dropped_newest,
+            Backpressure::DropOldest => &self.dropped_oldest,
+            Backpressure::Reject => &self.rejected,
+        };
+        counter.fetch_add(count, Ordering::Relaxed);
+    }
+}
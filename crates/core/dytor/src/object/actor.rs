@@ -6,23 +6,66 @@ use std::{
 
 use serde::de::DeserializeOwned;
 
-use crate::{context, registry::ActorRegistered, InitArgs, UniquelyNamed};
+use crate::{
+    context, lookup::Dependency, registry::ActorRegistered, InitArgs, MainArgs, UniquelyNamed,
+};
 
-use super::{ObjectConstructor, VTable};
+use super::{Hook, ObjectConstructor, VTable};
 
 pub trait Actor: Any + Unpin + Sized + UniquelyNamed + ActorRegistered {
-    type Config: Debug + DeserializeOwned + Send;
+    type Config: Debug + DeserializeOwned + Send + MaybeJsonSchema;
 
     fn init(args: InitArgs<Self>, config: Self::Config) -> anyhow::Result<Self>;
+
+    /// The lookups `init` makes. Declaring them lets [`crate::validate`] check them without
+    /// constructing anything, and makes sure `acyclic_local_key` dependencies are constructed
+    /// before this actor.
+    ///
+    /// An actor that derives [`crate::Dependencies`] itself has the derived lookups declared by
+    /// `register_actor!`, along with any returned here, so it only needs this for lookups its
+    /// fields don't make.
+    fn dependencies() -> Vec<Dependency> {
+        Vec::new()
+    }
+
+    /// Called once every actor in every context has been constructed, before this actor's context
+    /// handles any message. Messages sent from here are handled after every actor on the context
+    /// has started.
+    fn on_start(&mut self, _args: &mut MainArgs) {}
+
+    /// Called when the system shuts down, before any actor is dropped. Actors on a context are
+    /// stopped in reverse construction order, so dependents go first.
+    ///
+    /// Messages sent from here are still handled, by actors that may have stopped already,
+    /// whichever context they're on. Messages those send on to another context may be dropped,
+    /// and timers armed while stopping never fire.
+    fn on_stop(&mut self, _args: &mut MainArgs) {}
 }
 
+/// Only requires `JsonSchema` when the `schema` feature is enabled
+#[cfg(feature = "schema")]
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` does not implement `schemars::JsonSchema`",
+    note = "Actor configs must implement `JsonSchema` when dytor's `schema` feature is enabled."
+)]
+pub trait MaybeJsonSchema: schemars::JsonSchema {}
+
+#[cfg(feature = "schema")]
+impl<T: schemars::JsonSchema> MaybeJsonSchema for T {}
+
+#[cfg(not(feature = "schema"))]
+pub trait MaybeJsonSchema {}
+
+#[cfg(not(feature = "schema"))]
+impl<T> MaybeJsonSchema for T {}
+
 pub(crate) type ActorConstructor = for<'a, 'b> unsafe fn(
     InitArgs<'a, ()>,
     dest: &'b mut [u8],
     config: Box<dyn Any>,
 ) -> anyhow::Result<()>;
 
-pub(crate) fn create_vtable<T: Actor>() -> VTable {
+pub(crate) fn create_vtable<T: Actor>(dependencies: fn() -> Vec<Dependency>) -> VTable {
     let constructor = ObjectConstructor::Actor(|args, dest, config| {
         assert_eq!(dest.len(), std::mem::size_of::<T>());
         let config: Box<T::Config> = config.downcast().unwrap();
@@ -37,5 +80,7 @@ pub(crate) fn create_vtable<T: Actor>() -> VTable {
         unsafe { &mut *dest }.write(res);
         Ok(())
     });
-    VTable::new_impl::<T, T::Config>(constructor)
+    let on_start: Hook = |ptr, args| T::on_start(unsafe { &mut *ptr.cast::<T>() }, args);
+    let on_stop: Hook = |ptr, args| T::on_stop(unsafe { &mut *ptr.cast::<T>() }, args);
+    VTable::new_impl::<T, T::Config>(constructor, dependencies, (on_start, on_stop))
 }
@@ -8,7 +8,8 @@ use serde::de::DeserializeOwned;
 
 pub use dytor_proc_macros::UniquelyNamed;
 
-use self::actor::ActorConstructor;
+use self::actor::{ActorConstructor, MaybeJsonSchema};
+use crate::{lookup::Dependency, MainArgs};
 
 pub(crate) mod actor;
 
@@ -30,6 +31,9 @@ pub(crate) enum ObjectConstructor {
     Actor(ActorConstructor),
 }
 
+/// Calls a lifecycle method on the actor at a pointer
+pub(crate) type Hook = unsafe fn(*mut u8, &mut MainArgs);
+
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub(crate) struct VTable {
@@ -37,7 +41,15 @@ pub(crate) struct VTable {
         fn(serde_value::Value) -> anyhow::Result<Box<dyn Any + Send>>,
     pub(crate) constructor: ObjectConstructor,
     pub(crate) drop: unsafe fn(*mut u8),
+    pub(crate) on_start: Hook,
+    pub(crate) on_stop: Hook,
     pub(crate) type_id: TypeId,
+    pub(crate) name: fn() -> &'static str,
+    pub(crate) rust_type: fn() -> &'static str,
+    pub(crate) config_type: fn() -> &'static str,
+    pub(crate) dependencies: fn() -> Vec<Dependency>,
+    #[cfg(feature = "schema")]
+    pub(crate) config_schema: fn(&mut schemars::SchemaGenerator) -> schemars::Schema,
     size: usize,
     align: usize,
 }
@@ -47,8 +59,13 @@ impl VTable {
         Layout::from_size_align(self.size, self.align).unwrap()
     }
 
-    const fn new_impl<T: Any + UniquelyNamed, Config: 'static + Debug + DeserializeOwned + Send>(
+    const fn new_impl<
+        T: Any + UniquelyNamed,
+        Config: 'static + Debug + DeserializeOwned + Send + MaybeJsonSchema,
+    >(
         constructor: ObjectConstructor,
+        dependencies: fn() -> Vec<Dependency>,
+        (on_start, on_stop): (Hook, Hook),
     ) -> Self {
         Self {
             deserialize_yaml_value: |d| match Config::deserialize(d) {
@@ -60,7 +77,15 @@ impl VTable {
                 let this = ptr.cast::<T>();
                 unsafe { std::ptr::drop_in_place(this) };
             },
+            on_start,
+            on_stop,
             type_id: TypeId::of::<T>(),
+            name: T::name,
+            rust_type: std::any::type_name::<T>,
+            config_type: std::any::type_name::<Config>,
+            dependencies,
+            #[cfg(feature = "schema")]
+            config_schema: |generator| generator.subschema_for::<Config>(),
             size: std::mem::size_of::<T>(),
             align: std::mem::align_of::<T>(),
             constructor,
@@ -0,0 +1,119 @@
+use std::{
+    ffi::{CStr, CString},
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use crate::{
+    registry::{self, Registration},
+    Error,
+};
+
+/// What a shared library added to the registry when it was loaded
+#[derive(Clone, Debug)]
+pub struct Plugin {
+    pub path: PathBuf,
+    pub actors: Vec<PluginActor>,
+    /// Type names of the resources
+    pub resources: Vec<&'static str>,
+}
+
+#[derive(Clone, Debug)]
+pub struct PluginActor {
+    pub typename: &'static str,
+    /// Type names of the trait objects the actor can be looked up as, e.g. `dyn my_crate::Trait`
+    pub traits: Vec<&'static str>,
+}
+
+static LOADED: Mutex<Vec<Plugin>> = Mutex::new(Vec::new());
+
+/// Loads a shared library and adds the actors and resources it registers to the registry.
+///
+/// Libraries are never unloaded, since the registry refers to their code. Systems that are
+/// already running don't see anything a library registers, so they can't spawn its actors; only
+/// later calls to `run` do.
+pub fn load(path: impl AsRef<Path>) -> Result<Plugin, Error> {
+    let path = path.as_ref();
+    let mut loaded = LOADED.lock().unwrap();
+
+    // anything registered before now (e.g. by the executable) shouldn't be credited to this library
+    registry::sync().map_err(|source| Error::Registration { path: None, source })?;
+    open(path)?;
+    let registrations = registry::sync().map_err(|source| Error::Registration {
+        path: Some(path.to_owned()),
+        source,
+    })?;
+
+    let mut plugin = Plugin {
+        path: path.to_owned(),
+        actors: Vec::new(),
+        resources: Vec::new(),
+    };
+    for registration in registrations {
+        match registration {
+            Registration::Actor { typename, traits } => {
+                plugin.actors.push(PluginActor { typename, traits })
+            }
+            Registration::Resource(typename) => plugin.resources.push(typename),
+        }
+    }
+    loaded.push(plugin.clone());
+    Ok(plugin)
+}
+
+/// Every library loaded with [`load`], in load order
+pub fn loaded() -> Vec<Plugin> {
+    LOADED.lock().unwrap().clone()
+}
+
+fn open(path: &Path) -> Result<(), Error> {
+    let load_error = |reason: String| Error::LoadLibrary {
+        path: path.to_owned(),
+        reason,
+    };
+    let name = CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| load_error("path contains a nul byte".into()))?;
+    let handle = unsafe {
+        libc::dlopen(
+            name.as_ptr(),
+            libc::RTLD_LOCAL | libc::RTLD_NODELETE | libc::RTLD_LAZY,
+        )
+    };
+    if handle.is_null() {
+        return Err(load_error(dlerror()));
+    }
+    Ok(())
+}
+
+fn dlerror() -> String {
+    let err = unsafe { libc::dlerror() };
+    if err.is_null() {
+        return "unknown error".into();
+    }
+    unsafe { CStr::from_ptr(err) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn load_errors() {
+        let path = Path::new("/nonexistent/libplugin.so");
+        let Err(Error::LoadLibrary { path: p, reason }) = load(path) else {
+            panic!("a missing library was loaded");
+        };
+        assert_eq!(p, path);
+        // the reason comes from dlerror
+        assert!(reason.contains("libplugin.so"), "{reason}");
+
+        let Err(Error::LoadLibrary { reason, .. }) = load("lib\0plugin.so") else {
+            panic!("a path with a nul byte was loaded");
+        };
+        assert_eq!(reason, "path contains a nul byte");
+        assert!(loaded().iter().all(|plugin| plugin.path != path));
+    }
+}
@@ -1,25 +1,104 @@
+//! The queue a context sends its own actors messages through. It's only touched by the context's
+//! thread, so it's a plain FIFO of chunks taken from the context's [`Slab`].
+
+use std::collections::VecDeque;
+
+use super::slab::Chunk;
+pub use super::slab::Slab;
+
 pub struct LocalQueue<T> {
-    items: Vec<T>,
+    chunks: VecDeque<Box<Chunk<T>>>,
+    len: usize,
 }
 
 impl<T> Default for LocalQueue<T> {
     fn default() -> Self {
         Self {
-            items: Default::default(),
+            chunks: Default::default(),
+            len: 0,
         }
     }
 }
 
 impl<T: 'static> LocalQueue<T> {
-    pub fn send(&mut self, value: T) {
-        self.items.push(value);
+    pub fn send(&mut self, slab: &mut Slab<T>, value: T) {
+        if self.chunks.back().is_none_or(|chunk| chunk.is_full()) {
+            self.chunks.push_back(slab.take());
+        }
+        self.chunks.back_mut().unwrap().push(value);
+        self.len += 1;
+    }
+
+    /// Returns values in the order they were sent. Chunks that have been read are given back to
+    /// `slab`, except the last, which is kept for the next send.
+    pub fn recv(&mut self, slab: &mut Slab<T>) -> Option<T> {
+        loop {
+            let last = self.chunks.len() == 1;
+            let front = self.chunks.front_mut()?;
+            if let Some(value) = front.pop() {
+                self.len -= 1;
+                return Some(value);
+            }
+            if last {
+                front.reset();
+                return None;
+            }
+            slab.give(self.chunks.pop_front().unwrap());
+        }
     }
 
-    pub fn recv(&mut self) -> Option<T> {
-        self.items.pop()
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
     }
 
     pub fn unbounded() -> Self {
-        Self { items: Vec::new() }
+        Self::default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::queue::slab::MAX_FREE_CHUNKS;
+
+    #[test]
+    fn fifo() {
+        let mut slab = Slab::default();
+        let mut queue = LocalQueue::unbounded();
+        let mut received = Vec::new();
+        for i in 0..100 {
+            queue.send(&mut slab, i);
+        }
+        received.push(queue.recv(&mut slab).unwrap());
+        // sending while draining, as a handler does, goes behind what's already queued
+        for i in 100..200 {
+            queue.send(&mut slab, i);
+        }
+        received.extend(std::iter::from_fn(|| queue.recv(&mut slab)));
+        assert_eq!(received, (0..200).collect::<Vec<_>>());
+        assert!(queue.is_empty());
+
+        // read chunks are reused rather than allocated again
+        let free = slab.free_chunks();
+        assert!(free > 0);
+        for i in 0..100 {
+            queue.send(&mut slab, i);
+        }
+        assert!(slab.free_chunks() < free);
+    }
+
+    #[test]
+    fn free_chunks_capped() {
+        let mut slab = Slab::default();
+        let mut queue = LocalQueue::unbounded();
+        for i in 0..100_000 {
+            queue.send(&mut slab, i);
+        }
+        while queue.recv(&mut slab).is_some() {}
+        assert_eq!(slab.free_chunks(), MAX_FREE_CHUNKS);
     }
 }
@@ -1,2 +1,38 @@
+//! Queues that carry messages to a context.
+//!
+//! # Ordering
+//!
+//! Messages from one sender to one actor are handled in the order they were sent. A sender is
+//! either a context, whose actors send with `send_msg`, `broadcast` and `ask`, or a thread sending
+//! through an [`crate::Accessor`].
+//!
+//! - Messages to an actor on the sender's own context go through its FIFO [`local::LocalQueue`],
+//!   which is drained after every message the context handles.
+//! - Messages to another context are held until the handler that sent them returns, then pushed
+//!   in the order they were sent onto the receiving context's [`remote`] queue, which is FIFO per
+//!   producing thread.
+//!
+//! Nothing is guaranteed between different senders: messages from two contexts, or from a context
+//! and an accessor, may interleave in any way. A bounded queue's backpressure policy may drop
+//! messages, but never reorders the ones it keeps.
+
+mod bounded;
 pub mod local;
 pub mod remote;
+mod slab;
+mod unbounded;
+
+use std::ops::Deref;
+
+/// Keeps a value on its own cache line, so atomics written by different threads don't contend.
+/// 128 bytes covers adjacent-line prefetching on x86_64.
+#[repr(align(128))]
+pub(crate) struct CachePadded<T>(pub(crate) T);
+
+impl<T> Deref for CachePadded<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
@@ -1,30 +1,484 @@
-use std::sync::mpsc;
+//! Multi-producer single-consumer queues that carry messages between contexts. Producers never
+//! take a lock. When its queue is empty the consumer waits according to its [`WaitStrategy`];
+//! if that involves parking, the next send unparks it.
 
-pub fn channel<T>() -> (Tx<T>, Rx<T>) {
-    let (tx, rx) = mpsc::channel();
-    (Tx(tx), Rx(rx))
+use std::{
+    hint,
+    sync::{
+        atomic::{fence, AtomicBool, AtomicUsize, Ordering},
+        Arc, OnceLock,
+    },
+    thread::{self, Thread},
+    time::{Duration, Instant},
+};
+
+use super::{bounded::Ring, unbounded::List};
+use crate::{
+    config::{Backpressure, Queue, WaitStrategy},
+    metrics::QueueMetrics,
+};
+
+pub fn channel<T>(queue: Queue, wait_strategy: WaitStrategy) -> (Tx<T>, Rx<T>) {
+    let (flavor, backpressure) = match queue {
+        Queue::Unbounded => (Flavor::Unbounded(List::new()), Backpressure::Block),
+        Queue::Bounded {
+            capacity,
+            backpressure,
+        } => (Flavor::Bounded(Ring::new(capacity)), backpressure),
+    };
+    let chan = Arc::new(Chan {
+        flavor,
+        backpressure,
+        metrics: QueueMetrics::default(),
+        senders: AtomicUsize::new(1),
+        receiver_alive: AtomicBool::new(true),
+        waiting: AtomicBool::new(false),
+        interrupted: AtomicBool::new(false),
+        receiver: OnceLock::new(),
+    });
+    (
+        Tx(chan.clone()),
+        Rx {
+            chan,
+            wait_strategy,
+        },
+    )
+}
+
+enum Flavor<T> {
+    Unbounded(List<T>),
+    Bounded(Ring<T>),
+}
+
+struct Chan<T> {
+    flavor: Flavor<T>,
+    /// Only applies to bounded queues
+    backpressure: Backpressure,
+    metrics: QueueMetrics,
+    senders: AtomicUsize,
+    receiver_alive: AtomicBool,
+    /// Set by the receiver just before it parks
+    waiting: AtomicBool,
+    /// Set by [`Tx::interrupt`], and never cleared
+    interrupted: AtomicBool,
+    /// The thread the receiver parks on, set the first time it does
+    receiver: OnceLock<Thread>,
+}
+
+impl<T> Chan<T> {
+    fn wake(&self) {
+        // pairs with the fence in `Rx::recv_deadline`: either the receiver sees the new value, or
+        // we see that it's waiting
+        fence(Ordering::SeqCst);
+        if self.waiting.load(Ordering::Relaxed) && self.waiting.swap(false, Ordering::Relaxed) {
+            if let Some(thread) = self.receiver.get() {
+                thread.unpark();
+            }
+        }
+    }
 }
 
-pub struct Tx<T>(mpsc::Sender<T>);
-pub struct Rx<T>(mpsc::Receiver<T>);
+pub struct Tx<T>(Arc<Chan<T>>);
+/// Once it has waited for a value by parking, it has to stay on the thread it parked on
+pub struct Rx<T> {
+    chan: Arc<Chan<T>>,
+    wait_strategy: WaitStrategy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendError {
+    /// The receiver has been dropped
+    Disconnected,
+    /// The queue is full and its policy is [`Backpressure::Reject`]
+    Full,
+}
+
+impl std::fmt::Display for SendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SendError::Disconnected => write!(f, "receiving context is gone"),
+            SendError::Full => write!(f, "receiving context's queue is full"),
+        }
+    }
+}
+
+impl std::error::Error for SendError {}
 
 #[derive(Debug)]
-pub struct SendError;
+pub enum RecvTimeoutError {
+    Timeout,
+    Disconnected,
+    /// [`Tx::interrupt`] has been called
+    Interrupted,
+}
 
 impl<T: 'static + Send> Tx<T> {
+    /// Fails if the receiver has been dropped. A full bounded queue makes this wait for space,
+    /// whatever its backpressure policy.
     pub fn send(&self, value: T) -> Result<(), SendError> {
-        self.0.send(value).map_err(|_| SendError)
+        if !self.0.receiver_alive.load(Ordering::Relaxed) {
+            return Err(SendError::Disconnected);
+        }
+        match &self.0.flavor {
+            Flavor::Unbounded(list) => list.push(value),
+            Flavor::Bounded(ring) => {
+                let mut value = value;
+                while let Err(v) = ring.push(value) {
+                    if !self.0.receiver_alive.load(Ordering::Relaxed) {
+                        return Err(SendError::Disconnected);
+                    }
+                    value = v;
+                    thread::yield_now();
+                }
+            }
+        }
+        self.0.wake();
+        Ok(())
+    }
+
+    /// Sends under the queue's [`Backpressure`] policy. Every value the policy drops, whether
+    /// `value` itself or one evicted to make room for it, is passed to `dropped`.
+    pub fn send_with_backpressure(
+        &self,
+        value: T,
+        mut dropped: impl FnMut(T),
+    ) -> Result<(), SendError> {
+        let ring = match (&self.0.flavor, self.0.backpressure) {
+            (Flavor::Bounded(ring), policy) if policy != Backpressure::Block => ring,
+            _ => return self.send(value),
+        };
+        if !self.0.receiver_alive.load(Ordering::Relaxed) {
+            return Err(SendError::Disconnected);
+        }
+
+        let mut value = value;
+        loop {
+            match ring.push(value) {
+                Ok(()) => break,
+                Err(v) => match self.0.backpressure {
+                    Backpressure::DropOldest => {
+                        value = v;
+                        if let Some(oldest) = ring.pop() {
+                            dropped(oldest);
+                        }
+                    }
+                    Backpressure::Reject => {
+                        dropped(v);
+                        return Err(SendError::Full);
+                    }
+                    _ => {
+                        dropped(v);
+                        return Ok(());
+                    }
+                },
+            }
+        }
+        self.0.wake();
+        Ok(())
+    }
+
+    /// Like [`Self::send`], but fails with [`SendError::Full`] rather than waiting for room in a
+    /// bounded queue, whatever its backpressure policy
+    pub fn try_send(&self, value: T) -> Result<(), SendError> {
+        if !self.0.receiver_alive.load(Ordering::Relaxed) {
+            return Err(SendError::Disconnected);
+        }
+        match &self.0.flavor {
+            Flavor::Unbounded(list) => list.push(value),
+            Flavor::Bounded(ring) => ring.push(value).map_err(|_| SendError::Full)?,
+        }
+        self.0.wake();
+        Ok(())
+    }
+
+    /// Makes [`Rx::recv_interruptible`] return from now on, even if values are queued. Unlike
+    /// sending, this never waits for room in the queue.
+    pub fn interrupt(&self) {
+        self.0.interrupted.store(true, Ordering::Relaxed);
+        self.0.wake();
+    }
+
+    pub fn backpressure(&self) -> Backpressure {
+        self.0.backpressure
+    }
+
+    pub fn metrics(&self) -> &QueueMetrics {
+        &self.0.metrics
     }
 }
 
 impl<T: 'static + Send> Rx<T> {
+    /// Returns `None` once every sender has been dropped and the queue is empty
     pub fn recv(&mut self) -> Option<T> {
-        self.0.recv().ok()
+        self.recv_deadline(None, false).ok()
+    }
+
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.recv_deadline(Some(Instant::now() + timeout), false)
+    }
+
+    /// Waits until `deadline`, or for as long as it takes if there is none, unless the queue has
+    /// been interrupted. An interrupt takes priority over queued values, which are left for
+    /// [`Self::recv`].
+    pub fn recv_interruptible(&mut self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        self.recv_deadline(deadline, true)
+    }
+
+    pub fn try_recv(&mut self) -> Option<T> {
+        match &self.chan.flavor {
+            // safety: Rx isn't Clone and this takes &mut self, so there's only ever one consumer
+            Flavor::Unbounded(list) => unsafe { list.pop() },
+            Flavor::Bounded(ring) => ring.pop(),
+        }
+    }
+
+    fn recv_deadline(
+        &mut self,
+        deadline: Option<Instant>,
+        interruptible: bool,
+    ) -> Result<T, RecvTimeoutError> {
+        let mut spins = 0;
+        loop {
+            if interruptible && self.chan.interrupted.load(Ordering::Relaxed) {
+                return Err(RecvTimeoutError::Interrupted);
+            }
+            if let Some(value) = self.try_recv() {
+                return Ok(value);
+            }
+            if self.chan.senders.load(Ordering::Acquire) == 0 {
+                // a sender may have pushed just before dropping
+                return self.try_recv().ok_or(RecvTimeoutError::Disconnected);
+            }
+
+            let polling = match self.wait_strategy {
+                WaitStrategy::Block => false,
+                WaitStrategy::SpinThenPark { spins: max } => {
+                    spins += 1;
+                    spins <= max
+                }
+                WaitStrategy::Yield => {
+                    thread::yield_now();
+                    true
+                }
+                WaitStrategy::BusyPoll => true,
+            };
+            if polling {
+                hint::spin_loop();
+                if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    return Err(RecvTimeoutError::Timeout);
+                }
+                continue;
+            }
+
+            self.register_thread();
+            self.chan.waiting.store(true, Ordering::Relaxed);
+            fence(Ordering::SeqCst);
+            if let Some(value) = self.try_recv() {
+                self.chan.waiting.store(false, Ordering::Relaxed);
+                return Ok(value);
+            }
+            if self.chan.senders.load(Ordering::Acquire) == 0
+                || interruptible && self.chan.interrupted.load(Ordering::Relaxed)
+            {
+                self.chan.waiting.store(false, Ordering::Relaxed);
+                continue;
+            }
+            match deadline {
+                None => thread::park(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        self.chan.waiting.store(false, Ordering::Relaxed);
+                        return Err(RecvTimeoutError::Timeout);
+                    }
+                    thread::park_timeout(deadline - now);
+                }
+            }
+            self.chan.waiting.store(false, Ordering::Relaxed);
+        }
+    }
+
+    pub fn metrics(&self) -> &QueueMetrics {
+        &self.chan.metrics
+    }
+
+    /// Stops accepting values and drops those already queued. Values may hold senders to this
+    /// queue, which would otherwise keep it alive.
+    pub fn close(&mut self) {
+        self.chan.receiver_alive.store(false, Ordering::Relaxed);
+        while self.try_recv().is_some() {}
+    }
+
+    fn register_thread(&self) {
+        let current = thread::current();
+        let receiver = self.chan.receiver.get_or_init(|| current.clone());
+        assert_eq!(
+            receiver.id(),
+            current.id(),
+            "a receiver has to park on the thread it first parked on"
+        );
     }
 }
 
 impl<T> Clone for Tx<T> {
     fn clone(&self) -> Self {
+        self.0.senders.fetch_add(1, Ordering::Relaxed);
         Self(self.0.clone())
     }
 }
+
+impl<T> Drop for Tx<T> {
+    fn drop(&mut self) {
+        if self.0.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.0.wake();
+        }
+    }
+}
+
+impl<T> Drop for Rx<T> {
+    fn drop(&mut self) {
+        self.chan.receiver_alive.store(false, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::AtomicU32;
+
+    use super::*;
+
+    fn fifo_per_sender(queue: Queue, wait_strategy: WaitStrategy) {
+        const SENDERS: usize = 4;
+        const PER_SENDER: usize = 10_000;
+
+        let (tx, mut rx) = channel::<(usize, usize)>(queue, wait_strategy);
+        let handles: Vec<_> = (0..SENDERS)
+            .map(|sender| {
+                let tx = tx.clone();
+                thread::spawn(move || {
+                    for i in 0..PER_SENDER {
+                        tx.send((sender, i)).unwrap();
+                    }
+                })
+            })
+            .collect();
+        drop(tx);
+
+        let mut next = [0; SENDERS];
+        while let Some((sender, i)) = rx.recv() {
+            assert_eq!(next[sender], i);
+            next[sender] += 1;
+        }
+        assert_eq!(next, [PER_SENDER; SENDERS]);
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn unbounded() {
+        fifo_per_sender(Queue::Unbounded, WaitStrategy::Block);
+        fifo_per_sender(Queue::Unbounded, WaitStrategy::SpinThenPark { spins: 100 });
+    }
+
+    #[test]
+    fn bounded() {
+        let queue = Queue::Bounded {
+            capacity: 16,
+            backpressure: Backpressure::Block,
+        };
+        fifo_per_sender(queue, WaitStrategy::Block);
+        fifo_per_sender(queue, WaitStrategy::Yield);
+        let single = Queue::Bounded {
+            capacity: 1,
+            backpressure: Backpressure::Block,
+        };
+        fifo_per_sender(single, WaitStrategy::Block);
+    }
+
+    #[test]
+    fn unread_values_dropped() {
+        static DROPPED: AtomicU32 = AtomicU32::new(0);
+        struct Counted;
+        impl Drop for Counted {
+            fn drop(&mut self) {
+                DROPPED.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        let bounded = Queue::Bounded {
+            capacity: 64,
+            backpressure: Backpressure::Block,
+        };
+        for queue in [Queue::Unbounded, bounded] {
+            DROPPED.store(0, Ordering::Relaxed);
+            let (tx, rx) = channel(queue, WaitStrategy::default());
+            for _ in 0..40 {
+                tx.send(Counted).unwrap();
+            }
+            drop(rx);
+            assert!(tx.send(Counted).is_err());
+            drop(tx);
+            assert_eq!(DROPPED.load(Ordering::Relaxed), 41);
+        }
+    }
+
+    #[test]
+    fn backpressure() {
+        let send_all = |backpressure| {
+            let (tx, mut rx) = channel(
+                Queue::Bounded {
+                    capacity: 4,
+                    backpressure,
+                },
+                WaitStrategy::default(),
+            );
+            let mut dropped = Vec::new();
+            let results: Vec<_> = (0..6)
+                .map(|i| tx.send_with_backpressure(i, |v| dropped.push(v)))
+                .collect();
+            let received: Vec<_> = std::iter::from_fn(|| rx.try_recv()).collect();
+            (results, received, dropped)
+        };
+
+        let (results, received, dropped) = send_all(Backpressure::DropNewest);
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!((received, dropped), (vec![0, 1, 2, 3], vec![4, 5]));
+
+        let (results, received, dropped) = send_all(Backpressure::DropOldest);
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!((received, dropped), (vec![2, 3, 4, 5], vec![0, 1]));
+
+        let (results, received, dropped) = send_all(Backpressure::Reject);
+        assert_eq!(results[4], Err(SendError::Full));
+        assert_eq!((received, dropped), (vec![0, 1, 2, 3], vec![4, 5]));
+    }
+
+    #[test]
+    fn interrupt() {
+        let queue = Queue::Bounded {
+            capacity: 2,
+            backpressure: Backpressure::Block,
+        };
+        let (tx, mut rx) = channel(queue, WaitStrategy::Block);
+        let parked = thread::spawn(move || {
+            let res = rx.recv_interruptible(None);
+            (rx, res)
+        });
+        thread::sleep(Duration::from_millis(20));
+        tx.interrupt();
+        let (mut rx, res) = parked.join().unwrap();
+        assert!(matches!(res, Err(RecvTimeoutError::Interrupted)));
+
+        // the queue is full, but interrupting doesn't wait for it
+        tx.send(1).unwrap();
+        tx.send(2).unwrap();
+        assert_eq!(tx.try_send(3), Err(SendError::Full));
+        tx.interrupt();
+        assert!(matches!(
+            rx.recv_interruptible(None),
+            Err(RecvTimeoutError::Interrupted)
+        ));
+        assert_eq!(rx.recv(), Some(1));
+    }
+}
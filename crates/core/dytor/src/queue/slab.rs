@@ -0,0 +1,93 @@
+//! Fixed-size chunks of slots, recycled within a context. Once a context has warmed up, queueing
+//! a message on it takes a slot from a chunk it already has rather than allocating, and growing
+//! adds a chunk instead of reallocating and moving everything already queued. Chunks beyond
+//! `MAX_FREE_CHUNKS` are freed as they're given back, so a burst doesn't pin its peak memory
+//! for the rest of the context's life.
+
+use std::mem::MaybeUninit;
+
+const CHUNK_SLOTS: usize = 64;
+
+/// How many unused chunks a slab keeps, enough for a few thousand queued messages
+pub(crate) const MAX_FREE_CHUNKS: usize = 64;
+
+/// Values are read from `head` and written at `tail`; slots outside `head..tail` are uninit
+pub(crate) struct Chunk<T> {
+    slots: [MaybeUninit<T>; CHUNK_SLOTS],
+    head: usize,
+    tail: usize,
+}
+
+impl<T> Chunk<T> {
+    pub(crate) fn is_full(&self) -> bool {
+        self.tail == CHUNK_SLOTS
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.head == self.tail
+    }
+
+    /// The caller checks the chunk isn't full
+    pub(crate) fn push(&mut self, value: T) {
+        self.slots[self.tail].write(value);
+        self.tail += 1;
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<T> {
+        if self.is_empty() {
+            return None;
+        }
+        let value = unsafe { self.slots[self.head].assume_init_read() };
+        self.head += 1;
+        Some(value)
+    }
+
+    /// Makes an empty chunk's slots available again
+    pub(crate) fn reset(&mut self) {
+        debug_assert!(self.is_empty());
+        self.head = 0;
+        self.tail = 0;
+    }
+}
+
+impl<T> Drop for Chunk<T> {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
+
+/// Chunks not currently in use by any of a context's queues
+pub struct Slab<T> {
+    free: Vec<Box<Chunk<T>>>,
+}
+
+impl<T> Default for Slab<T> {
+    fn default() -> Self {
+        Self { free: Vec::new() }
+    }
+}
+
+impl<T> Slab<T> {
+    pub(crate) fn take(&mut self) -> Box<Chunk<T>> {
+        self.free.pop().unwrap_or_else(|| {
+            Box::new(Chunk {
+                slots: [const { MaybeUninit::uninit() }; CHUNK_SLOTS],
+                head: 0,
+                tail: 0,
+            })
+        })
+    }
+
+    #[cfg(test)]
+    pub(crate) fn free_chunks(&self) -> usize {
+        self.free.len()
+    }
+
+    /// Keeps `chunk` for reuse, unless the slab already has as many as it keeps
+    pub(crate) fn give(&mut self, mut chunk: Box<Chunk<T>>) {
+        if self.free.len() < MAX_FREE_CHUNKS {
+            chunk.reset();
+            self.free.push(chunk);
+        }
+    }
+}
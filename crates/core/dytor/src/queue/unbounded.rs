@@ -0,0 +1,145 @@
+//! Unbounded queue made of a linked list of fixed-size blocks. Producers claim positions by
+//! bumping a shared tail index; whoever claims the last slot of a block links in the next one.
+//! Blocks are written once and freed by the consumer as soon as it has read them.
+
+use std::{
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    ptr,
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering},
+    thread,
+};
+
+use super::CachePadded;
+
+/// Positions per block, one of which is never used: while the tail index points at it, the next
+/// block is being installed
+const LAP: usize = 32;
+const BLOCK_CAP: usize = LAP - 1;
+
+struct Slot<T> {
+    ready: AtomicBool,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+struct Block<T> {
+    next: AtomicPtr<Block<T>>,
+    slots: [Slot<T>; BLOCK_CAP],
+}
+
+impl<T> Block<T> {
+    fn new() -> Box<Self> {
+        // safety: all zeroes is a null `next`, unready slots and uninit values
+        unsafe { Box::new_zeroed().assume_init() }
+    }
+}
+
+struct Tail<T> {
+    index: AtomicUsize,
+    block: AtomicPtr<Block<T>>,
+}
+
+struct Head<T> {
+    index: usize,
+    block: *mut Block<T>,
+}
+
+pub(crate) struct List<T> {
+    tail: CachePadded<Tail<T>>,
+    /// Only touched by the consumer
+    head: CachePadded<UnsafeCell<Head<T>>>,
+}
+
+unsafe impl<T: Send> Send for List<T> {}
+unsafe impl<T: Send> Sync for List<T> {}
+
+impl<T> List<T> {
+    pub(crate) fn new() -> Self {
+        let block = Box::into_raw(Block::new());
+        Self {
+            tail: CachePadded(Tail {
+                index: AtomicUsize::new(0),
+                block: AtomicPtr::new(block),
+            }),
+            head: CachePadded(UnsafeCell::new(Head { index: 0, block })),
+        }
+    }
+
+    pub(crate) fn push(&self, value: T) {
+        // the index is loaded before the block; the installer stores them in the opposite order,
+        // so if the index is still current when it's claimed, the block is the one it belongs to
+        let mut index = self.tail.index.load(Ordering::Acquire);
+        let mut block = self.tail.block.load(Ordering::Acquire);
+        let mut next_block = None;
+        loop {
+            let offset = index % LAP;
+            if offset == BLOCK_CAP {
+                // someone else is installing the next block
+                thread::yield_now();
+                index = self.tail.index.load(Ordering::Acquire);
+                block = self.tail.block.load(Ordering::Acquire);
+                continue;
+            }
+            if offset + 1 == BLOCK_CAP && next_block.is_none() {
+                next_block = Some(Block::new());
+            }
+
+            match self.tail.index.compare_exchange_weak(
+                index,
+                index + 1,
+                Ordering::SeqCst,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => unsafe {
+                    if offset + 1 == BLOCK_CAP {
+                        let next = Box::into_raw(next_block.take().unwrap());
+                        self.tail.block.store(next, Ordering::Release);
+                        self.tail.index.fetch_add(1, Ordering::Release);
+                        (*block).next.store(next, Ordering::Release);
+                    }
+                    let slot = &(*block).slots[offset];
+                    (*slot.value.get()).write(value);
+                    slot.ready.store(true, Ordering::Release);
+                    return;
+                },
+                Err(actual) => {
+                    index = actual;
+                    block = self.tail.block.load(Ordering::Acquire);
+                }
+            }
+        }
+    }
+
+    /// Returns `None` if the queue is empty, or the next value is still being written.
+    ///
+    /// Safety: must not be called from more than one thread at a time.
+    pub(crate) unsafe fn pop(&self) -> Option<T> {
+        let head = &mut *self.head.get();
+        if head.index % LAP == BLOCK_CAP {
+            // every slot of this block has been read, and the last one is only written after the
+            // next block is linked, so no producer touches this block again
+            let next = (*head.block).next.load(Ordering::Acquire);
+            debug_assert!(!next.is_null());
+            drop(Box::from_raw(head.block));
+            head.block = next;
+            head.index += 1;
+        }
+
+        let slot = &(*head.block).slots[head.index % LAP];
+        if !slot.ready.load(Ordering::Acquire) {
+            return None;
+        }
+        let value = (*slot.value.get()).assume_init_read();
+        head.index += 1;
+        Some(value)
+    }
+}
+
+impl<T> Drop for List<T> {
+    fn drop(&mut self) {
+        while unsafe { self.pop() }.is_some() {}
+        let head = self.head.0.get_mut();
+        unsafe { drop(Box::from_raw(head.block)) };
+        head.block = ptr::null_mut();
+    }
+}
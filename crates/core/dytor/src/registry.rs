@@ -1,8 +1,8 @@
-use core::sync::atomic::AtomicPtr;
+use core::sync::atomic::{AtomicPtr, Ordering};
 use std::any::{type_name, Any, TypeId};
-use std::mem::MaybeUninit;
-use std::ptr::DynMetadata;
-use std::sync::Arc;
+use std::mem::{self, MaybeUninit};
+use std::ptr::{self, DynMetadata};
+use std::sync::{Arc, Mutex};
 
 pub(crate) use __private::ActorRegistered;
 pub(crate) use __private::InterfaceMetadata;
@@ -14,16 +14,46 @@ use crate::{
     Actor,
 };
 use std::collections::HashMap;
+use std::fmt;
 
 static INIT_FNS: AtomicPtr<ListNode> = AtomicPtr::new(core::ptr::null_mut());
 
-#[derive(Default)]
+/// The latest snapshot of the registry. Snapshots are leaked, since running contexts hold
+/// references into whichever snapshot they started with.
+static CURRENT: AtomicPtr<Registry> = AtomicPtr::new(core::ptr::null_mut());
+
+static STATE: Mutex<Option<RegistryState>> = Mutex::new(None);
+
+struct RegistryState {
+    builder: RegistryBuilder,
+    /// The newest node in `INIT_FNS` that has been applied to `builder`
+    applied: *mut ListNode,
+    /// Every snapshot published before the current one, which systems started earlier still use
+    superseded: Vec<&'static Registry>,
+}
+
+// safety: `applied` points to a static ListNode
+unsafe impl Send for RegistryState {}
+
+#[derive(Default, Clone)]
 pub struct RegistryBuilder {
     pub(crate) actor_types: HashMap<TypeId, VTable>,
     pub(crate) trait_types: HashMap<TraitId, Vec<InterfaceMetadata>>,
     pub(crate) name_to_type_id: HashMap<&'static str, TypeId>,
     pub(crate) resource_constructors:
         HashMap<TypeId, Arc<dyn Send + Sync + Fn() -> Box<dyn Any + Send + Sync>>>,
+    pub(crate) resource_names: HashMap<TypeId, &'static str>,
+    pub(crate) registrations: Vec<Registration>,
+}
+
+/// Something registered by a `register_actor!` or `register_resource!` invocation
+#[derive(Clone, Debug)]
+pub(crate) enum Registration {
+    Actor {
+        typename: &'static str,
+        traits: Vec<&'static str>,
+    },
+    Resource(&'static str),
 }
 
 pub(crate) struct Registry {
@@ -32,40 +62,40 @@ pub(crate) struct Registry {
     pub(crate) name_to_type_id: HashMap<&'static str, TypeId>,
     pub(crate) resource_constructors:
         HashMap<TypeId, Arc<dyn Send + Sync + Fn() -> Box<dyn Any + Send + Sync>>>,
+    pub(crate) resource_names: HashMap<TypeId, &'static str>,
 }
 
 impl Registry {
     pub(crate) fn get() -> &'static Self {
-        use std::sync::atomic::Ordering;
-        use std::sync::OnceLock;
-
-        static REGISTRY: OnceLock<Registry> = OnceLock::new();
-        REGISTRY.get_or_init(|| {
-            let mut registry = RegistryBuilder::default();
-            let mut ptr = INIT_FNS.load(Ordering::Acquire);
-            while !ptr.is_null() {
-                let node = unsafe { &*ptr };
-                (node.f)(&mut registry).unwrap();
-                ptr = node.next.load(Ordering::Relaxed);
-            }
+        if let Some(registry) = unsafe { CURRENT.load(Ordering::Acquire).as_ref() } {
+            return registry;
+        }
+        if let Err(e) = sync() {
+            panic!("{e:#}");
+        }
+        unsafe { &*CURRENT.load(Ordering::Acquire) }
+    }
 
-            let RegistryBuilder {
-                actor_types,
-                name_to_type_id,
-                trait_types,
-                resource_constructors,
-            } = registry;
-
-            Registry {
-                actor_types,
-                trait_types: trait_types
-                    .into_iter()
-                    .map(|(k, v)| (k, v.into_boxed_slice()))
-                    .collect(),
-                name_to_type_id,
-                resource_constructors,
-            }
-        })
+    fn from_builder(builder: &RegistryBuilder) -> Self {
+        let RegistryBuilder {
+            actor_types,
+            name_to_type_id,
+            trait_types,
+            resource_constructors,
+            resource_names,
+            registrations: _,
+        } = builder.clone();
+
+        Registry {
+            actor_types,
+            trait_types: trait_types
+                .into_iter()
+                .map(|(k, v)| (k, v.into_boxed_slice()))
+                .collect(),
+            name_to_type_id,
+            resource_constructors,
+            resource_names,
+        }
     }
 
     pub(crate) fn by_name(&self, name: &str) -> Option<(TypeId, &VTable)> {
@@ -75,11 +105,113 @@ impl Registry {
     }
 }
 
+/// A registered actor type
+#[derive(Clone, Debug)]
+pub struct ActorDescription {
+    /// The name configs refer to the actor by
+    pub typename: &'static str,
+    pub rust_type: &'static str,
+    pub config_type: &'static str,
+    /// Trait objects the actor can be looked up as, e.g. `dyn my_crate::Trait`
+    pub traits: Vec<&'static str>,
+    pub size: usize,
+    pub align: usize,
+}
+
+/// Every registered actor type, sorted by typename
+pub fn actors() -> Vec<ActorDescription> {
+    let registry = Registry::get();
+    let mut res: Vec<_> = registry
+        .actor_types
+        .values()
+        .map(|vtable| {
+            let mut traits: Vec<_> = registry
+                .trait_types
+                .values()
+                .flat_map(|impls| impls.iter())
+                .filter(|meta| meta.type_id == vtable.type_id)
+                .map(|meta| meta.trait_name)
+                .collect();
+            traits.sort();
+            let layout = vtable.layout();
+            ActorDescription {
+                typename: (vtable.name)(),
+                rust_type: (vtable.rust_type)(),
+                config_type: (vtable.config_type)(),
+                traits,
+                size: layout.size(),
+                align: layout.align(),
+            }
+        })
+        .collect();
+    res.sort_by_key(|actor| actor.typename);
+    res
+}
+
+/// Type names of every registered resource, sorted
+pub fn resources() -> Vec<&'static str> {
+    let mut res: Vec<_> = Registry::get().resource_names.values().copied().collect();
+    res.sort();
+    res
+}
+
+impl fmt::Display for ActorDescription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.typename)?;
+        writeln!(f, "    type: {}", self.rust_type)?;
+        writeln!(f, "    config: {}", self.config_type)?;
+        writeln!(f, "    size: {}, align: {}", self.size, self.align)?;
+        write!(f, "    traits: {}", self.traits.join(", "))
+    }
+}
+
+/// Applies every registration made since the last sync (e.g. by the constructors of a library that
+/// has just been loaded) and publishes a new snapshot of the registry.
+///
+/// If any of them fail, none are applied. They won't be retried either.
+pub(crate) fn sync() -> anyhow::Result<Vec<Registration>> {
+    let mut state = STATE.lock().unwrap();
+    let state = state.get_or_insert_with(|| RegistryState {
+        builder: RegistryBuilder::default(),
+        applied: ptr::null_mut(),
+        superseded: Vec::new(),
+    });
+
+    // nodes are pushed to the front of the list, so the new ones come before `applied`
+    let head = INIT_FNS.load(Ordering::Acquire);
+    let mut new_nodes = Vec::new();
+    let mut ptr = head;
+    while ptr != state.applied {
+        let node = unsafe { &*ptr };
+        new_nodes.push(node);
+        ptr = node.next.load(Ordering::Relaxed);
+    }
+    state.applied = head;
+
+    if new_nodes.is_empty() && !CURRENT.load(Ordering::Relaxed).is_null() {
+        return Ok(Vec::new());
+    }
+
+    let mut builder = state.builder.clone();
+    for node in new_nodes.into_iter().rev() {
+        (node.f)(&mut builder)?;
+    }
+    let registrations = mem::take(&mut builder.registrations);
+
+    let registry = Box::leak(Box::new(Registry::from_builder(&builder)));
+    let prev = CURRENT.swap(registry, Ordering::AcqRel);
+    if let Some(prev) = unsafe { prev.as_ref() } {
+        state.superseded.push(prev);
+    }
+    state.builder = builder;
+    Ok(registrations)
+}
+
 #[macro_export]
 macro_rules! register_resource {
     ($closure:expr) => {
         $crate::paste::paste! {
-            #[allow(non_snake_case)]
+            #[allow(non_snake_case, unexpected_cfgs)]
             mod __declare_resource {
                 use $crate::registry::__private::*;
                 use super::*;
@@ -102,7 +234,7 @@ macro_rules! register_actor {
     };
     ($struct:ty { $($trait_impl:ty),* $(,)? }) => {
         $crate::paste::paste! {
-            #[allow(non_snake_case)]
+            #[allow(non_snake_case, unexpected_cfgs)]
             mod [<__declare_actor_ $struct>] {
                 use super::*;
                 use $crate::registry::__private::*;
@@ -115,7 +247,10 @@ macro_rules! register_actor {
                     ].into_iter()
                 }
 
-                static NODE: ListNode = ListNode::new(|r| init_actor::<$struct>(r, get_metadata()));
+                static NODE: ListNode = ListNode::new(|r| {
+                    let dependencies = (&DependenciesOf::<$struct>::default()).dependencies();
+                    init_actor::<$struct>(r, dependencies, get_metadata())
+                });
 
                 #[ctor::ctor]
                 fn $struct() {
@@ -136,9 +271,10 @@ pub(crate) struct DynMetaPlaceholder(MaybeUninit<DynMetadata<dyn Any>>);
 
 pub mod __private {
     use super::*;
+    use crate::lookup::{Dependencies, Dependency};
     pub use crate::object::TraitId;
     use crate::Dyn;
-    use core::{ptr, sync::atomic::Ordering};
+    use core::{marker::PhantomData, ptr, sync::atomic::Ordering};
     pub use ctor;
     pub use libc;
 
@@ -163,6 +299,7 @@ pub mod __private {
         (
             TraitId::of::<D>(),
             InterfaceMetadata {
+                trait_name: type_name::<D>(),
                 dyn_meta: unsafe {
                     std::mem::transmute::<
                         std::ptr::DynMetadata<D>,
@@ -174,7 +311,45 @@ pub mod __private {
         )
     }
 
+    /// Picks the lookups `register_actor!` declares for an actor: those of its
+    /// [`crate::Dependencies`] impl if it derives that itself, on top of
+    /// [`Actor::dependencies`]. Which trait's method applies is decided by autoref, so derived
+    /// dependencies are preferred without specialization.
+    pub struct DependenciesOf<T>(PhantomData<T>);
+
+    impl<T> Default for DependenciesOf<T> {
+        fn default() -> Self {
+            Self(PhantomData)
+        }
+    }
+
+    pub trait DerivedDependencies {
+        fn dependencies(&self) -> fn() -> Vec<Dependency>;
+    }
+
+    impl<T: Actor + Dependencies> DerivedDependencies for DependenciesOf<T> {
+        fn dependencies(&self) -> fn() -> Vec<Dependency> {
+            || {
+                let mut res = <T as Dependencies>::list();
+                res.extend(T::dependencies());
+                res
+            }
+        }
+    }
+
+    pub trait DeclaredDependencies {
+        fn dependencies(&self) -> fn() -> Vec<Dependency>;
+    }
+
+    impl<T: Actor> DeclaredDependencies for &DependenciesOf<T> {
+        fn dependencies(&self) -> fn() -> Vec<Dependency> {
+            T::dependencies
+        }
+    }
+
+    #[derive(Clone)]
     pub struct InterfaceMetadata {
+        pub(crate) trait_name: &'static str,
         pub(crate) type_id: TypeId,
         pub(crate) dyn_meta: DynMetaPlaceholder,
     }
@@ -205,28 +380,47 @@ pub mod __private {
             "Resource {} registered twice",
             type_name::<T>()
         );
+        registry
+            .resource_names
+            .insert(TypeId::of::<T>(), type_name::<T>());
+        registry
+            .registrations
+            .push(Registration::Resource(type_name::<T>()));
         Ok(())
     }
 
     pub fn init_actor<T: Actor>(
         registry: &mut RegistryBuilder,
+        dependencies: fn() -> Vec<Dependency>,
         traits: impl Iterator<Item = (TraitId, InterfaceMetadata)>,
     ) -> anyhow::Result<()> {
         let prev = registry
             .actor_types
-            .insert(TypeId::of::<T>(), actor::create_vtable::<T>());
-        for (trait_id, meta) in traits {
-            registry.trait_types.entry(trait_id).or_default().push(meta);
-        }
+            .insert(TypeId::of::<T>(), actor::create_vtable::<T>(dependencies));
         anyhow::ensure!(
             prev.is_none(),
             "Actor {} registered twice",
             type_name::<T>()
         );
-        assert!(registry
+        let prev = registry
             .name_to_type_id
-            .insert(T::name(), TypeId::of::<T>())
-            .is_none());
+            .insert(T::name(), TypeId::of::<T>());
+        anyhow::ensure!(
+            prev.is_none(),
+            "Actor name {} registered twice, the second time by {}",
+            T::name(),
+            type_name::<T>()
+        );
+
+        let mut trait_names = Vec::new();
+        for (trait_id, meta) in traits {
+            trait_names.push(meta.trait_name);
+            registry.trait_types.entry(trait_id).or_default().push(meta);
+        }
+        registry.registrations.push(Registration::Actor {
+            typename: T::name(),
+            traits: trait_names,
+        });
         Ok(())
     }
 
@@ -248,3 +442,103 @@ pub mod __private {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::iter;
+
+    use super::*;
+    use crate::{
+        registry::__private::{init_actor, init_resource},
+        InitArgs, UniquelyNamed,
+    };
+
+    trait Describe {}
+
+    struct Described;
+
+    impl UniquelyNamed for Described {
+        fn name() -> &'static str {
+            "Described"
+        }
+    }
+
+    register_actor!(Described { dyn Describe });
+
+    impl Describe for Described {}
+
+    impl Actor for Described {
+        type Config = u64;
+
+        fn init(_args: InitArgs<Self>, _config: u64) -> anyhow::Result<Self> {
+            Ok(Self)
+        }
+    }
+
+    /// Takes `Described`'s name, so it's only ever registered into a builder of its own
+    struct Impostor;
+
+    impl UniquelyNamed for Impostor {
+        fn name() -> &'static str {
+            "Described"
+        }
+    }
+
+    impl ActorRegistered for Impostor {}
+
+    impl Actor for Impostor {
+        type Config = ();
+
+        fn init(_args: InitArgs<Self>, _config: ()) -> anyhow::Result<Self> {
+            Ok(Self)
+        }
+    }
+
+    #[test]
+    fn described() {
+        let actor = actors()
+            .into_iter()
+            .find(|actor| actor.typename == "Described")
+            .unwrap();
+        assert_eq!(actor.rust_type, type_name::<Described>());
+        assert_eq!(actor.config_type, "u64");
+        assert_eq!(actor.traits, [type_name::<dyn Describe>()]);
+        assert_eq!((actor.size, actor.align), (0, 1));
+        assert_eq!(
+            actor.to_string(),
+            format!(
+                "Described\n    type: {}\n    config: u64\n    size: 0, align: 1\n    traits: {}",
+                type_name::<Described>(),
+                type_name::<dyn Describe>(),
+            )
+        );
+    }
+
+    #[test]
+    fn registered_twice() {
+        let mut builder = RegistryBuilder::default();
+        init_actor::<Described>(&mut builder, Described::dependencies, iter::empty()).unwrap();
+        let err = init_actor::<Described>(&mut builder, Described::dependencies, iter::empty())
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("Actor {} registered twice", type_name::<Described>())
+        );
+
+        let mut builder = RegistryBuilder::default();
+        init_actor::<Described>(&mut builder, Described::dependencies, iter::empty()).unwrap();
+        let err = init_actor::<Impostor>(&mut builder, Impostor::dependencies, iter::empty())
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Actor name Described registered twice, the second time by {}",
+                type_name::<Impostor>()
+            )
+        );
+
+        init_resource::<u8>(&mut builder, || 0).unwrap();
+        let err = init_resource::<u8>(&mut builder, || 1).unwrap_err();
+        assert_eq!(err.to_string(), "Resource u8 registered twice");
+    }
+}
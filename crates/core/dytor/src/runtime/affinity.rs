@@ -0,0 +1,52 @@
+use std::{io, mem};
+
+#[derive(Clone, Copy)]
+pub(crate) struct CpuSet(libc::cpu_set_t);
+
+impl CpuSet {
+    fn empty() -> Self {
+        // Safety: cpu_set_t is a plain bitmask, so all zeroes is the empty set
+        Self(unsafe { mem::zeroed() })
+    }
+
+    pub(crate) fn contains(&self, cpu: usize) -> bool {
+        cpu < libc::CPU_SETSIZE as usize && unsafe { libc::CPU_ISSET(cpu, &self.0) }
+    }
+
+    fn insert(&mut self, cpu: usize) {
+        unsafe { libc::CPU_SET(cpu, &mut self.0) };
+    }
+
+    /// The CPUs the process is currently allowed to run on
+    pub(crate) fn available() -> io::Result<Self> {
+        let mut set = Self::empty();
+        let res = unsafe { libc::sched_getaffinity(0, mem::size_of_val(&set.0), &mut set.0) };
+        if res != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(set)
+    }
+
+    /// Builds a set from `cpus`, checking each one is in `available`
+    pub(crate) fn new(cpus: &[usize], available: &CpuSet) -> Result<Self, String> {
+        if cpus.is_empty() {
+            return Err("thread_affinity is empty".into());
+        }
+        let mut set = Self::empty();
+        for &cpu in cpus {
+            if !available.contains(cpu) {
+                return Err(format!("CPU {cpu} is not available to this process"));
+            }
+            set.insert(cpu);
+        }
+        Ok(set)
+    }
+
+    pub(crate) fn pin_current_thread(&self) -> io::Result<()> {
+        let res = unsafe { libc::sched_setaffinity(0, mem::size_of_val(&self.0), &self.0) };
+        if res != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
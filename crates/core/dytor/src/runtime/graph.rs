@@ -1,12 +1,14 @@
 use std::{
     cell::Cell,
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, BTreeSet},
 };
 
 use crate::{context::ActorId, lookup::DependenceRelation};
 
-pub fn has_cycles(relations: &[DependenceRelation]) -> bool {
-    let mut graph: HashMap<ActorId, Node> = HashMap::new();
+/// Returns a cycle as a path that starts and ends at the same actor, e.g. `[a, b, c, a]`.
+/// The graph is traversed in id order so the same config always reports the same cycle.
+pub fn find_cycle(relations: &[DependenceRelation]) -> Option<Vec<ActorId>> {
+    let mut graph: BTreeMap<ActorId, Node> = BTreeMap::new();
 
     for relation in relations {
         graph
@@ -17,17 +19,20 @@ pub fn has_cycles(relations: &[DependenceRelation]) -> bool {
         graph.entry(relation.to).or_default();
     }
 
+    let mut path = Vec::new();
     for actor in graph.keys() {
-        if dfs(*actor, &graph) {
-            return true;
+        if dfs(*actor, &graph, &mut path) {
+            let repeated = *path.last().unwrap();
+            let start = path.iter().position(|&x| x == repeated).unwrap();
+            return Some(path.split_off(start));
         }
     }
-    false
+    None
 }
 
 #[derive(Default)]
 struct Node {
-    children: HashSet<ActorId>,
+    children: BTreeSet<ActorId>,
     state: Cell<State>,
 }
 
@@ -39,19 +44,25 @@ enum State {
     Visited,
 }
 
-fn dfs(node: ActorId, graph: &HashMap<ActorId, Node>) -> bool {
+/// On finding a cycle, `path` ends with the route from the first visited node to the repeated node
+fn dfs(id: ActorId, graph: &BTreeMap<ActorId, Node>, path: &mut Vec<ActorId>) -> bool {
     use State as S;
-    let node = graph.get(&node).unwrap();
+    let node = graph.get(&id).unwrap();
     match node.state.get() {
-        S::Visiting => true,
+        S::Visiting => {
+            path.push(id);
+            true
+        }
         S::Visited => false,
         S::Unvisited => {
             node.state.set(S::Visiting);
+            path.push(id);
             for child in &node.children {
-                if dfs(*child, graph) {
+                if dfs(*child, graph, path) {
                     return true;
                 }
             }
+            path.pop();
             node.state.set(S::Visited);
             false
         }
@@ -62,7 +73,7 @@ fn dfs(node: ActorId, graph: &HashMap<ActorId, Node>) -> bool {
 mod test {
     use super::*;
 
-    fn has_cycles(t: impl IntoIterator<Item = (u32, u32)>) -> bool {
+    fn find_cycle(t: impl IntoIterator<Item = (u32, u32)>) -> Option<Vec<u32>> {
         let f = |a| ActorId::new(a + 1).unwrap();
         let v: Vec<DependenceRelation> = t
             .into_iter()
@@ -71,7 +82,12 @@ mod test {
                 to: f(b),
             })
             .collect();
-        super::has_cycles(&v)
+        let cycle = super::find_cycle(&v)?;
+        Some(cycle.into_iter().map(|id| id.as_u32() - 1).collect())
+    }
+
+    fn has_cycles(t: impl IntoIterator<Item = (u32, u32)>) -> bool {
+        find_cycle(t).is_some()
     }
 
     #[test]
@@ -88,4 +104,13 @@ mod test {
     fn test_3cycle_with_offshoot() {
         assert!(has_cycles([(1, 2), (2, 3), (3, 4), (3, 1)]));
     }
+
+    #[test]
+    fn cycle_path() {
+        assert_eq!(
+            find_cycle([(0, 1), (1, 2), (2, 3), (3, 4), (4, 2)]),
+            Some(vec![2, 3, 4, 2])
+        );
+        assert_eq!(find_cycle([(1, 2), (1, 3), (2, 4), (3, 4)]), None);
+    }
 }
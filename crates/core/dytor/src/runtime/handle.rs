@@ -0,0 +1,187 @@
+use std::{
+    fmt,
+    marker::PhantomData,
+    panic,
+    ptr::{NonNull, Pointee},
+    sync::{mpsc, Arc},
+    thread::{self, JoinHandle},
+};
+
+use itertools::Itertools;
+
+use super::{prepare, run_context, Phases, ShutdownHandle};
+use crate::{
+    context::{ControlBlock, MsgTx, QueueItem},
+    lookup::{ActorTree, Filter, Key, LiveTree, Lookup},
+    Accessor, Config, Error,
+};
+
+/// Runs a system in the background, next to whatever else the process does
+pub struct Runtime;
+
+/// Why [`RuntimeHandle::accessor_by_name`] didn't find exactly one actor
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ByNameError {
+    NotFound(String),
+    /// More than one actor of the requested type has the name, e.g. in different scopes
+    Ambiguous(String),
+}
+
+impl fmt::Display for ByNameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ByNameError::NotFound(name) => write!(f, "No actor is named {name}"),
+            ByNameError::Ambiguous(name) => write!(f, "More than one actor is named {name}"),
+        }
+    }
+}
+
+impl std::error::Error for ByNameError {}
+
+impl Runtime {
+    /// Starts every context on its own thread, returning once every actor has been constructed.
+    /// If any actor fails to start, every actor that was constructed is dropped and the error is
+    /// returned.
+    pub fn start(config: Config) -> Result<RuntimeHandle, Error> {
+        let args = prepare(config)?;
+        let shutdown = args[0].shutdown.clone();
+        let tree = args[0].tree.clone().unwrap();
+        let make_tx = args[0].make_tx.clone();
+        let block = args[0].control_block_ptr.0;
+        unsafe { block.as_ref() }.add_accessor();
+
+        let (started_tx, started_rx) = mpsc::channel();
+        let phases = Arc::new(Phases::new(args.len(), Some(started_tx)));
+        let threads: Vec<_> = args
+            .into_iter()
+            .map(|a| {
+                let phases = phases.clone();
+                thread::Builder::new()
+                    .name(format!("dytor-ctx-{}", a.id.as_u32()))
+                    .spawn(move || run_context(a, &phases))
+                    .unwrap()
+            })
+            .collect();
+        drop(phases);
+
+        let handle = RuntimeHandle {
+            tree,
+            make_tx,
+            block,
+            shutdown,
+            threads,
+        };
+        match started_rx.recv() {
+            Ok(()) => Ok(handle),
+            // the sender is dropped without sending if the system failed to start
+            Err(mpsc::RecvError) => Err(handle.join_threads().unwrap_err()),
+        }
+    }
+}
+
+/// Lets code outside the system send to its actors, and shut it down.
+///
+/// The handle counts as an accessor, so the system can't shut down by running out of things to
+/// do while it's alive. Dropping it, or calling [`Self::join`], lets it.
+pub struct RuntimeHandle {
+    tree: Arc<LiveTree>,
+    make_tx: Arc<[Box<dyn Send + Sync + 'static + Fn() -> MsgTx>]>,
+    /// Held like an accessor's
+    block: NonNull<ControlBlock>,
+    shutdown: ShutdownHandle,
+    threads: Vec<JoinHandle<Result<(), Error>>>,
+}
+
+// safety: the control block is only touched atomically
+unsafe impl Send for RuntimeHandle {}
+unsafe impl Sync for RuntimeHandle {}
+
+impl RuntimeHandle {
+    /// An accessor to the actor of type `T` whose config gives it this instance name. `T` may be
+    /// a trait the actor is registered with.
+    pub fn accessor_by_name<T: ?Sized + 'static>(
+        &self,
+        name: &str,
+    ) -> Result<Accessor<T>, ByNameError>
+    where
+        ActorTree: Lookup<T, <T as Pointee>::Metadata>,
+    {
+        let filter = Filter {
+            name: Some(name.into()),
+            scope: None,
+        };
+        let tree = self.tree.snapshot();
+        let found = tree
+            .lookup(None)
+            .filter(|&(id, _)| tree.matches(None, id, &filter))
+            .at_most_one()
+            .map(|found| found.map(|(_, key)| key));
+        match found {
+            Ok(Some(key)) => Ok(self.accessor_for_key(key)),
+            Ok(None) => Err(ByNameError::NotFound(name.into())),
+            Err(_) => Err(ByNameError::Ambiguous(name.into())),
+        }
+    }
+
+    /// An accessor to every actor of type `T`, or registered with trait `T`, including actors
+    /// spawned so far
+    pub fn accessors<T: ?Sized + 'static>(&self) -> impl '_ + Iterator<Item = Accessor<T>>
+    where
+        ActorTree: Lookup<T, <T as Pointee>::Metadata>,
+    {
+        let keys: Vec<_> = self.tree.snapshot().lookup(None).collect();
+        keys.into_iter().map(|(_, key)| self.accessor_for_key(key))
+    }
+
+    fn accessor_for_key<T: ?Sized>(&self, key: Key<T>) -> Accessor<T> {
+        unsafe { self.block.as_ref() }.add_accessor();
+        Accessor {
+            offset: key.loc.offset,
+            metadata: key.meta,
+            ctx_queue: (self.make_tx[key.loc.context_id.as_index()])(),
+            control_block_ptr: self.block,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Shuts the system down without waiting for it; see [`ShutdownHandle`]
+    pub fn shutdown(&self) {
+        self.shutdown.shutdown()
+    }
+
+    /// For code that needs to shut the system down but shouldn't be able to send to it
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Waits for the system to shut down, either because [`Self::shutdown`] was called or
+    /// because it has nothing left to do. Resumes the panic if a context panicked.
+    pub fn join(self) {
+        if let Err(e) = self.join_threads() {
+            unreachable!("a context failed after startup: {e}");
+        }
+    }
+
+    /// Gives up this handle's hold on the system, then waits for every context to exit. Errors
+    /// are only returned during startup; the one from the lowest numbered context is reported.
+    fn join_threads(mut self) -> Result<(), Error> {
+        let threads = std::mem::take(&mut self.threads);
+        drop(self);
+        let results: Vec<_> = threads.into_iter().map(|t| t.join()).collect();
+        // a panic is resumed once every context has exited
+        results
+            .into_iter()
+            .collect::<thread::Result<Vec<_>>>()
+            .unwrap_or_else(|panic| panic::resume_unwind(panic))
+            .into_iter()
+            .collect()
+    }
+}
+
+impl Drop for RuntimeHandle {
+    fn drop(&mut self) {
+        // like an accessor, this goes to the first context, which may already be gone
+        let _ = (self.make_tx[0])().send(QueueItem::AccessorDropped);
+        unsafe { ControlBlock::release_holder(self.block) };
+    }
+}
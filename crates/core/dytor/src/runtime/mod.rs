@@ -1,61 +1,207 @@
 use std::{
-    alloc::Layout,
-    any::TypeId,
+    any::{Any, TypeId},
     collections::HashMap,
     mem,
+    panic::{self, AssertUnwindSafe},
+    ptr,
     sync::{
-        atomic::{self, Ordering},
-        Arc, LazyLock,
+        atomic::{self, AtomicBool, AtomicUsize, Ordering},
+        mpsc, Arc, Barrier, LazyLock, Mutex,
     },
+    thread,
+    time::Instant,
 };
 
 use crate::{
     arena::{Arena, Offset},
-    config::ActorConfig,
+    config,
+    config::Supervision,
     context::{
-        ActorId, Context, ContextData, ContextId, ContextLink, ControlBlockPtr, InitArgs, InitData,
-        LazyResource, MsgRx, MsgTx, QueueItem,
+        ActorId, Context, ContextData, ContextId, ContextLink, ControlBlock, ControlBlockPtr,
+        InitArgs, InitData, LazyResource, MsgRx, MsgTx, QueueItem,
     },
-    lookup::{ActorData, ActorTree, Loc},
+    lookup::{ActorData, ActorTree, DependencyKind, LiveTree, Loc},
+    message::{Msg, Origin},
     object::{ObjectConstructor, VTable},
-    queue::{local::LocalQueue, remote},
-    Config, Registry,
+    queue::{
+        local::{LocalQueue, Slab},
+        remote::{self, RecvTimeoutError},
+    },
+    timer::TimerWheel,
+    Config, Error, Registry,
 };
 
+mod affinity;
 mod graph;
+mod handle;
+pub(crate) mod scope;
+mod shutdown;
+mod signals;
+mod spawn;
+pub(crate) mod supervision;
+mod validate;
+
+pub use handle::{ByNameError, Runtime, RuntimeHandle};
+pub use shutdown::ShutdownHandle;
+pub use spawn::SpawnError;
+pub use validate::validate;
 
+/// Like [`try_run`], but panics if the system fails to start
 pub fn run(config: Config) {
-    let args = create_context_args(config);
+    if let Err(e) = try_run(config) {
+        panic!("{e}");
+    }
+}
 
-    std::thread::scope(|s| {
-        let mut args = args.into_iter();
-        let fst = args.next().unwrap();
+/// Starts the system in the background like [`Runtime::start`], keeping only a handle to shut it
+/// down. Nothing outside the system holds it up, so it also shuts down by itself once nothing is
+/// left to happen.
+pub fn start(config: Config) -> Result<ShutdownHandle, Error> {
+    Runtime::start(config).map(|handle| handle.shutdown_handle())
+}
+
+/// Runs every context until the system shuts down, each on its own thread like with
+/// [`Runtime::start`], so the calling thread keeps its name and CPU affinity. If any actor fails
+/// to start, every actor that was constructed is dropped and the error is returned. If one panics
+/// instead, the panic is resumed once every context has exited.
+///
+/// Actors can shut the system down with [`crate::MainArgs::request_shutdown`]; see
+/// [`ShutdownHandle`] for the order things happen in. A panic that no
+/// [`Supervision`] policy catches shuts it down the same way, then is resumed.
+pub fn try_run(config: Config) -> Result<(), Error> {
+    let args = prepare(config)?;
+    let phases = Phases::new(args.len(), None);
+    run_contexts(args, &phases)
+}
+
+/// Creates every context's args, checking everything that can be checked before any actor is
+/// constructed
+fn prepare(config: Config) -> Result<Vec<ContextConstructorArgs>, Error> {
+    let shutdown_on_signals = config.shutdown_on_signals;
+    let args = create_context_args(config)?;
+    let mut res = validate::check_dependencies(&args[0].tree.as_ref().unwrap().snapshot());
+    if res.is_ok() && shutdown_on_signals {
+        res = signals::watch(args[0].shutdown.clone()).map_err(Error::SignalHandlers);
+    }
+    if let Err(e) = res {
         for a in args {
-            s.spawn(|| run_thread(a));
+            a.discard();
         }
-        run_thread(fst);
-    });
+        return Err(e);
+    }
+    Ok(args)
 }
 
-fn create_context_args(config: Config) -> Vec<ContextConstructorArgs> {
-    let ns = config.root;
-    if !ns.children.is_empty() {
-        unimplemented!("Namespaces");
+fn run_contexts(args: Vec<ContextConstructorArgs>, phases: &Phases) -> Result<(), Error> {
+    std::thread::scope(|s| {
+        let handles: Vec<_> = args
+            .into_iter()
+            .map(|a| {
+                std::thread::Builder::new()
+                    .name(format!("dytor-ctx-{}", a.id.as_u32()))
+                    .spawn_scoped(s, || run_context(a, phases))
+                    .unwrap()
+            })
+            .collect();
+        let results: Vec<_> = handles.into_iter().map(|h| h.join()).collect();
+        // a panic is resumed once every context has exited; otherwise report the error from the
+        // lowest numbered context
+        results
+            .into_iter()
+            .collect::<thread::Result<Vec<_>>>()
+            .unwrap_or_else(|panic| panic::resume_unwind(panic))
+            .into_iter()
+            .collect()
+    })
+}
+
+struct Phases {
+    /// Every actor in every context is constructed
+    started: Barrier,
+    /// Every context has stopped handling messages, so no more are counted as unhandled events
+    stopped: Barrier,
+    failed: AtomicBool,
+    /// Told once the system has started, for [`Runtime::start`]
+    on_started: Mutex<Option<mpsc::Sender<()>>>,
+    /// Contexts that haven't exited yet
+    running: AtomicUsize,
+}
+
+impl Phases {
+    fn new(contexts: usize, on_started: Option<mpsc::Sender<()>>) -> Self {
+        Self {
+            started: Barrier::new(contexts),
+            stopped: Barrier::new(contexts),
+            failed: AtomicBool::new(false),
+            on_started: Mutex::new(on_started),
+            running: AtomicUsize::new(contexts),
+        }
     }
-    if !ns.imported_scopes.is_empty() {
-        unimplemented!("Namespaces");
+}
+
+/// Runs a context on the current thread. Whichever exits last marks the system as finished.
+fn run_context(args: ContextConstructorArgs, phases: &Phases) -> Result<(), Error> {
+    struct Exit<'a>(&'a Phases, ShutdownHandle);
+    impl Drop for Exit<'_> {
+        fn drop(&mut self) {
+            if self.0.running.fetch_sub(1, Ordering::AcqRel) == 1 {
+                self.1.set_finished();
+                signals::unwatch_finished();
+            }
+        }
     }
+    let _exit = Exit(phases, args.shutdown.clone());
+    run_thread(args, phases)
+}
+
+fn create_context_args(config: Config) -> Result<Vec<ContextConstructorArgs>, Error> {
+    let scope::FlatScopes {
+        scopes,
+        actors: actor_configs,
+    } = scope::flatten(config.root)?;
 
-    assert!(
-        config
+    if config.contexts.is_empty()
+        || !config
             .contexts
             .iter()
             .enumerate()
-            .all(|(i, b)| i == b.id.as_index()),
-        "Contexts are not named as 1 ..= n"
-    );
+            .all(|(i, b)| i == b.id.as_index())
+    {
+        return Err(Error::InvalidContextIds);
+    }
+
+    if let Some(ctx) = config
+        .contexts
+        .iter()
+        .find(|ctx| matches!(ctx.queue, config::Queue::Bounded { capacity: 0, .. }))
+    {
+        return Err(Error::InvalidQueueCapacity(ctx.id));
+    }
+
+    let available_cpus = affinity::CpuSet::available();
+    let affinities = config
+        .contexts
+        .iter()
+        .map(|ctx| {
+            let Some(cpus) = &ctx.thread_affinity else {
+                return Ok(None);
+            };
+            let invalid = |reason| Error::InvalidThreadAffinity {
+                context: ctx.id,
+                reason,
+            };
+            let available = available_cpus
+                .as_ref()
+                .map_err(|e| invalid(format!("cannot get the CPUs available: {e}")))?;
+            affinity::CpuSet::new(cpus, available)
+                .map(Some)
+                .map_err(invalid)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
 
-    let resource_map: HashMap<_, _> = Registry::get()
+    // libraries loaded from now on don't affect this system
+    let registry = Registry::get();
+    let resource_map: HashMap<_, _> = registry
         .resource_constructors
         .iter()
         .map(|(type_id, f)| {
@@ -73,14 +219,14 @@ fn create_context_args(config: Config) -> Vec<ContextConstructorArgs> {
     struct ContextData {
         tx: remote::Tx<QueueItem>,
         rx: Option<remote::Rx<QueueItem>>,
-        actors: Vec<(ActorId, ActorConfig)>,
+        actors: Vec<ActorConstructorInfo>,
     }
 
     let mut contexts: Vec<_> = config
         .contexts
         .iter()
-        .map(|_| {
-            let (tx, rx) = remote::channel();
+        .map(|ctx| {
+            let (tx, rx) = remote::channel(ctx.queue, ctx.wait_strategy);
             ContextData {
                 rx: Some(rx),
                 tx,
@@ -97,26 +243,52 @@ fn create_context_args(config: Config) -> Vec<ContextConstructorArgs> {
         .collect();
 
     let make_tx: Arc<[Box<dyn Send + Sync + 'static + Fn() -> MsgTx>]> = Arc::from(make_tx);
-    for (i, c) in ns.actors.into_iter().enumerate() {
+    let mut actor_scopes = Vec::with_capacity(actor_configs.len());
+    for (i, (scope, c)) in actor_configs.into_iter().enumerate() {
         let id = ActorId::new(i as u32 + 1).unwrap();
-        contexts[c.context.as_index()].actors.push((id, c));
+        let info = || scope::actor_info(&scopes, scope, id, &c.typename, c.context);
+        actor_scopes.push((scope, c.name.clone()));
+        let Some(ctx) = contexts.get_mut(c.context.as_index()) else {
+            return Err(Error::UnknownContext(info()));
+        };
+        let Some((_, vtable)) = registry.by_name(&c.typename) else {
+            return Err(Error::UnknownTypename(info()));
+        };
+        let restart_config =
+            (c.supervision == Some(Supervision::Restart)).then(|| c.config.clone());
+        let config =
+            (vtable.deserialize_yaml_value)(c.config).map_err(|source| Error::InvalidConfig {
+                actor: info(),
+                source,
+            })?;
+        ctx.actors.push(ActorConstructorInfo {
+            id,
+            offset: Offset::new(0), // filled later
+            vtable,
+            config,
+            supervision: c.supervision,
+            restart_config,
+        });
     }
 
     let mut constructor_args = Vec::new();
-    let mut tree = ActorTree { actors: Vec::new() };
-    let control_block_ptr = ControlBlockPtr::new();
+    let mut tree_actors = Vec::with_capacity(actor_scopes.len());
+    let control_block_ptr = ControlBlockPtr::new(contexts.len() as u32);
+    let shutdown = ShutdownHandle::new(contexts.iter().map(|ctx| ctx.tx.clone()).collect());
     for i in 0..contexts.len() {
         let id = ContextId::new(i as u32 + 1).unwrap();
         let actors = mem::take(&mut contexts[i].actors);
         let (arena, actors) = allocate_actors(actors);
         for actor in &actors {
-            tree.actors.push(ActorData {
+            tree_actors.push(ActorData {
                 id: actor.id,
                 vtable: actor.vtable,
                 loc: Loc {
                     context_id: id,
                     offset: actor.offset,
                 },
+                scope: actor_scopes[actor.id.as_index()].0,
+                name: actor_scopes[actor.id.as_index()].1.clone(),
             });
         }
         let links = contexts
@@ -146,23 +318,30 @@ fn create_context_args(config: Config) -> Vec<ContextConstructorArgs> {
             tree: None,
             control_block_ptr: control_block_ptr.clone(),
             resource_map: resource_map.clone(),
+            registry,
+            affinity: affinities[i],
+            shutdown: shutdown.clone(),
         })
     }
     control_block_ptr.release();
-    let tree = Arc::new(tree);
+    tree_actors.sort_by_key(|actor| actor.id);
+    let tree = Arc::new(LiveTree::new(ActorTree::new(tree_actors, scopes)));
 
     for ctx in &mut constructor_args {
         ctx.tree = Some(tree.clone());
     }
 
-    constructor_args
+    Ok(constructor_args)
 }
 
-struct ActorConstructorInfo {
+pub(crate) struct ActorConstructorInfo {
     id: ActorId,
     offset: Offset,
     vtable: &'static VTable,
-    cfg: ActorConfig,
+    config: Box<dyn Any + Send>,
+    supervision: Option<Supervision>,
+    /// The config as written, to construct the actor again for [`Supervision::Restart`]
+    restart_config: Option<serde_value::Value>,
 }
 
 struct ContextConstructorArgs {
@@ -171,29 +350,27 @@ struct ContextConstructorArgs {
     rx: MsgRx,
     arena: Arena,
     links: Box<[ContextLink]>,
-    tree: Option<Arc<ActorTree>>,
+    tree: Option<Arc<LiveTree>>,
     make_tx: Arc<[Box<dyn Send + Sync + 'static + Fn() -> MsgTx>]>,
     control_block_ptr: ControlBlockPtr,
     resource_map: Arc<HashMap<TypeId, LazyResource>>,
+    registry: &'static Registry,
+    affinity: Option<affinity::CpuSet>,
+    shutdown: ShutdownHandle,
+}
+
+impl ContextConstructorArgs {
+    /// Drops args without running the context
+    fn discard(self) {
+        let (block, _) = self.control_block_ptr.into_unowned();
+        // safety: nothing has been constructed, so only the contexts hold the block
+        unsafe { ControlBlock::release_holder(block) };
+    }
 }
 
 fn allocate_actors(
-    configs: impl IntoIterator<Item = (ActorId, ActorConfig)>,
+    mut constructor_info: Vec<ActorConstructorInfo>,
 ) -> (Arena, Vec<ActorConstructorInfo>) {
-    let registry = Registry::get();
-    let mut constructor_info: Vec<_> = configs
-        .into_iter()
-        .map(|(id, cfg)| {
-            let (_, vtable) = registry.by_name(&cfg.typename).unwrap();
-            assert!(matches!(vtable.constructor, ObjectConstructor::Actor(_)));
-            ActorConstructorInfo {
-                id,
-                offset: Offset(0), // filled later
-                vtable,
-                cfg,
-            }
-        })
-        .collect();
     let (arena, offsets) = Arena::from_layouts(&Vec::from_iter(
         constructor_info.iter().map(|info| info.vtable.layout()),
     ));
@@ -206,159 +383,1273 @@ fn allocate_actors(
     (arena, constructor_info)
 }
 
-fn create_context(info: ContextConstructorArgs) -> (Context, ControlBlockPtr) {
+/// Constructs `actor_id` if it's still pending. Failures are recorded in `init_data.error`.
+///
+/// This is reentrant: an actor that asks for an `AcyclicLocalKey` gets its dependency constructed
+/// first, so actors are constructed in dependency order whether or not they're listed that way.
+pub(crate) fn construct_actor(
+    init_data: &mut InitData,
+    actor_id: ActorId,
+    control_block_ptr: &ControlBlockPtr,
+    resources: &HashMap<TypeId, LazyResource>,
+) {
+    if init_data.error.is_some() {
+        return;
+    }
+    // an actor that's already under construction has been removed, so a cycle won't recurse forever
+    let Some(actor) = init_data.pending.remove(&actor_id) else {
+        return;
+    };
+
+    for dependency in (actor.vtable.dependencies)() {
+        if dependency.kind == DependencyKind::AcyclicLocal {
+            for (id, _) in dependency.resolve(&init_data.tree, actor_id) {
+                construct_actor(init_data, id, control_block_ptr, resources);
+            }
+        }
+    }
+
+    let offset = actor.offset;
+    let buf: *mut [u8] = init_data.arena.at_offset(offset, actor.vtable.layout());
+    // messages sent from `init` come from this actor
+    let sender = init_data.data.current_actor.replace(offset);
+    let outer_missing = mem::take(&mut init_data.dependency_missing);
+    let init_stage = InitArgs {
+        data: init_data,
+        actor_being_constructed: actor.id,
+        actor_offset: offset,
+        control_block_ptr,
+        resources,
+        _phantom: std::marker::PhantomData,
+    };
+    // safety: the buffer belongs to the arena's allocation, which nothing in init_data aliases
+    let constructed = match actor.vtable.constructor {
+        ObjectConstructor::Actor(f) => unsafe { f(init_stage, &mut *buf, actor.config) },
+    };
+    init_data.data.current_actor = sender;
+    let dependency_missing = mem::replace(&mut init_data.dependency_missing, outer_missing);
+    match constructed {
+        // its dependency failed, in which case that's the error to report, or is part of a cycle
+        Ok(()) if dependency_missing => unsafe {
+            (actor.vtable.drop)(init_data.arena.offset(offset))
+        },
+        Ok(()) => {
+            init_data.data.stopped.remove(&offset);
+            init_data.constructed.push((offset, actor.vtable));
+        }
+        Err(source) => {
+            // a dependency may have failed first, in which case its error is the one to report
+            if init_data.error.is_none() {
+                init_data.error = Some(Error::InitFailed {
+                    actor: scope::actor_info_in_tree(&init_data.tree, actor.id),
+                    source,
+                });
+            }
+        }
+    }
+}
+
+/// Constructs an actor on a context that's already running, to restart or spawn it. If its `init`
+/// fails or panics, or a local dependency of it isn't constructed, returns why.
+pub(crate) fn construct_running(
+    ctx: &mut Context,
+    actor: ActorConstructorInfo,
+) -> Result<(), String> {
+    let (id, offset, vtable) = (actor.id, actor.offset, actor.vtable);
+    // safety: the context's data and arena are moved back before anything else uses them, and
+    // nothing in between can unwind
+    let mut init_data = InitData {
+        tree: ctx.data.live_tree.snapshot(),
+        data: unsafe { ptr::read(&ctx.data) },
+        dependence_relations: Vec::new(),
+        arena: unsafe { ptr::read(&ctx.arena) },
+        constructed: Vec::new(),
+        pending: HashMap::from([(id, actor)]),
+        error: None,
+        dependency_missing: false,
+    };
+    let control_block_ptr = ControlBlockPtr(ctx.data.control_block);
+    let constructed = panic::catch_unwind(AssertUnwindSafe(|| {
+        construct_actor(&mut init_data, id, &control_block_ptr, &ctx.resources);
+    }));
+    mem::forget(control_block_ptr);
+    let InitData {
+        data,
+        arena,
+        constructed: newly_constructed,
+        error,
+        ..
+    } = init_data;
+    unsafe {
+        ptr::write(&mut ctx.data, data);
+        ptr::write(&mut ctx.arena, arena);
+    }
+    match (constructed, error) {
+        (Err(payload), _) => {
+            let message = payload
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| payload.downcast_ref::<String>().map(String::as_str));
+            Err(format!(
+                "init panicked: {}",
+                message.unwrap_or("Box<dyn Any>")
+            ))
+        }
+        (_, Some(Error::InitFailed { source, .. })) => Err(format!("init failed: {source:#}")),
+        (_, Some(e)) => Err(e.to_string()),
+        (Ok(()), None) if newly_constructed.is_empty() => {
+            Err("a local dependency wasn't constructed".into())
+        }
+        (Ok(()), None) => {
+            ctx.constructed.push(offset, vtable);
+            Ok(())
+        }
+    }
+}
+
+/// Pins the current thread and constructs the context's actors. A panic while constructing them is
+/// returned rather than resumed, so the caller can still wait for the other contexts.
+fn create_context(
+    info: ContextConstructorArgs,
+) -> (thread::Result<Result<Context, Error>>, ControlBlockPtr) {
     let ContextConstructorArgs {
-        mut arena,
+        arena,
         id,
-        actors,
+        mut actors,
         rx,
         links,
         tree,
         make_tx,
         control_block_ptr,
         resource_map,
+        registry,
+        affinity,
+        shutdown,
     } = info;
+    // pin before constructing anything so the arena is first touched on the right cores
+    if let Some(Err(e)) = affinity.map(|cpus| cpus.pin_current_thread()) {
+        let e = Error::InvalidThreadAffinity {
+            context: id,
+            reason: format!("cannot pin the context's thread: {e}"),
+        };
+        return (Ok(Err(e)), control_block_ptr);
+    }
+    let supervised: HashMap<_, _> = actors
+        .iter_mut()
+        .filter_map(|actor| {
+            let supervised = supervision::Supervised {
+                id: actor.id,
+                policy: actor.supervision?,
+                config: actor.restart_config.take(),
+            };
+            Some((actor.offset, supervised))
+        })
+        .collect();
     let data = ContextData {
         id,
         local_queue: LocalQueue::unbounded(),
-        unsent_messages: Vec::default(),
+        unsent_messages: (0..links.len() + 1)
+            .map(|_| LocalQueue::unbounded())
+            .collect(),
+        unsent_len: 0,
+        slab: Slab::default(),
+        timers: TimerWheel::new(),
+        shutdown,
+        current_actor: None,
+        // until each is constructed
+        stopped: actors.iter().map(|actor| actor.offset).collect(),
+        stopping: false,
+        origin: Origin::Accessor,
+        live_tree: tree.unwrap(),
+        spawned: Vec::new(),
+        registry,
+        make_tx,
+        control_block: control_block_ptr.0,
     };
 
+    let order: Vec<_> = actors.iter().map(|actor| actor.id).collect();
     let mut init_data = InitData {
+        tree: data.live_tree.snapshot(),
         data,
-        tree: tree.unwrap(),
         dependence_relations: Vec::new(),
-        make_tx,
+        arena,
+        constructed: Vec::with_capacity(order.len()),
+        pending: actors.into_iter().map(|actor| (actor.id, actor)).collect(),
+        error: None,
+        dependency_missing: false,
     };
 
-    let drop_fns: Vec<_> = actors.iter().map(|a| (a.offset, a.vtable.drop)).collect();
-
-    for actor in actors {
-        let init_stage = InitArgs {
-            data: &mut init_data,
-            actor_being_constructed: actor.id,
-            actor_offset: actor.offset,
-            control_block_ptr: &control_block_ptr,
-            resources: &resource_map,
-            _phantom: std::marker::PhantomData,
-        };
-        let cfg = (actor.vtable.deserialize_yaml_value)(actor.cfg.config).unwrap();
-        let offset = actor.offset;
-        let buf = arena.at_offset(offset, actor.vtable.layout());
-        match actor.vtable.constructor {
-            ObjectConstructor::Actor(f) => unsafe { f(init_stage, buf, cfg) }.unwrap(),
-        };
-    }
+    let constructing = panic::catch_unwind(AssertUnwindSafe(|| {
+        for actor_id in order {
+            construct_actor(&mut init_data, actor_id, &control_block_ptr, &resource_map);
+            if init_data.error.is_some() {
+                break;
+            }
+        }
+    }));
 
     let InitData {
         data,
         dependence_relations,
-        tree: _,
-        make_tx: _,
+        tree,
+        arena,
+        constructed,
+        pending: _,
+        error,
+        dependency_missing: _,
     } = init_data;
 
-    if graph::has_cycles(&dependence_relations) {
-        panic!("Cycle detected");
+    let mut res = error.map_or(Ok(()), Err);
+    if res.is_ok() {
+        if let Some(cycle) = graph::find_cycle(&dependence_relations) {
+            res = Err(Error::Cycle {
+                context: id,
+                cycle: cycle
+                    .into_iter()
+                    .map(|actor| scope::actor_info_in_tree(&tree, actor))
+                    .collect(),
+            });
+        }
     }
 
-    (
-        Context {
-            data,
-            arena,
-            drop_fns,
-            rx,
-            links,
-            _unsend_marker: Default::default(),
-        },
-        control_block_ptr,
-    )
+    let supervisor =
+        (!supervised.is_empty()).then(|| Box::new(supervision::Supervisor { actors: supervised }));
+    let ctx = Context {
+        data,
+        arena,
+        constructed: constructed.into_iter().collect(),
+        rx,
+        links,
+        supervisor,
+        resources: resource_map,
+        _unsend_marker: Default::default(),
+    };
+    // whatever was constructed before a panic is dropped here, like after an error
+    (constructing.map(|()| res.map(|()| ctx)), control_block_ptr)
 }
 
 // Yes, this function is super long and complex
 // However, it's better than breaking it up into smaller methods that rely on lots of subtle invariants
-fn run_thread(args: ContextConstructorArgs) {
-    let (mut ctx, control_block_ptr) = create_context(args);
+fn run_thread(args: ContextConstructorArgs, phases: &Phases) -> Result<(), Error> {
+    let (ctx, control_block_ptr) = create_context(args);
 
-    // handle first set of local messages. This is separate because we don't want to drop the control block prematurely
-    while let Some(msg) = ctx.data.local_queue.recv() {
-        msg(&mut ctx);
+    // no context may start handling messages until every actor in every context is constructed
+    if !matches!(ctx, Ok(Ok(_))) {
+        phases.failed.store(true, Ordering::Relaxed);
+    }
+    phases.started.wait();
+    if phases.failed.load(Ordering::Relaxed) {
+        // accessors handed out during init hold the block themselves
+        let (block, _) = control_block_ptr.into_unowned();
+        unsafe { ControlBlock::release_holder(block) };
+        return ctx
+            .unwrap_or_else(|panic| panic::resume_unwind(panic))
+            .map(drop);
+    }
+    let mut ctx = ctx.unwrap().unwrap();
+    if let Some(on_started) = phases.on_started.lock().unwrap().take() {
+        let _ = on_started.send(());
     }
 
+    // a panic that no supervisor catches is resumed once this context has shut down with the
+    // others, which would otherwise wait for it forever
+    let mut panicked = None;
+    catch(&mut panicked, || {
+        for offset in ctx.constructed.offsets() {
+            supervision::run(&mut ctx, |ctx| {
+                ctx.run_hook(offset, |vtable| vtable.on_start)
+            });
+        }
+
+        // handle first set of local messages. This is separate because we don't want to drop the control block prematurely
+        ctx.handle_local();
+    });
+
+    // armed timers count as unhandled events, like messages in flight. This context still holds
+    // the block, so messages dropped by a full queue can't take the count to zero.
+    let block = unsafe { control_block_ptr.0.as_ref() };
+    block.unhandled_events.fetch_add(
+        (ctx.data.timers.len() + ctx.data.unsent_len) as u32,
+        Ordering::Relaxed,
+    );
+    let dropped = flush_unsent(&mut ctx);
+    block.unhandled_events.fetch_sub(dropped, Ordering::Relaxed);
+
     // Safety: before a message is pushed to the queue, the control block ptr's ref count is increased.
     // Therefore, accessing control_block_ptr is safe until we decrement it again
-    let control_block_ptr = control_block_ptr.into_unowned();
-
-    loop {
-        let msg = ctx.rx.recv().unwrap();
-        let QueueItem::Msg(msg) = msg else {
-            // TODO: how do I indicate this branch is unlikely?
-            match msg {
-                QueueItem::Msg(_) => unreachable!(),
-                QueueItem::AccessorDropped => {
-                    let block = unsafe { control_block_ptr.as_ref() };
-                    if block.unhandled_events.fetch_sub(1, Ordering::Relaxed) <= 1 {
-                        for link in ctx.links.iter() {
-                            link.queue.send(QueueItem::Stop).unwrap();
-                        }
-                        // todo: drop
-                        let ptr = control_block_ptr.as_ptr() as _;
-                        let layout = Layout::for_value(block);
-                        unsafe {
-                            std::alloc::dealloc(ptr, layout);
+    let (control_block_ptr, last) = control_block_ptr.into_unowned();
+
+    if last || panicked.is_some() {
+        // nothing happened while starting, so nothing ever will
+        send_stop(&ctx.links);
+    } else {
+        let finished = catch(&mut panicked, || loop {
+            // due timers go first so a busy queue can't starve them
+            let due = ctx.data.timers.pop_due();
+            let armed = ctx.data.timers.len();
+            if let Some(timer) = due {
+                supervision::run(&mut ctx, |ctx| timer.fire(ctx));
+            } else {
+                let deadline = ctx.data.timers.next_deadline();
+                let msg = match ctx.rx.recv_interruptible(deadline) {
+                    Ok(msg) => msg,
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    // the system ran out of events, or a shutdown was requested
+                    Err(RecvTimeoutError::Interrupted) => break,
+                    Err(RecvTimeoutError::Disconnected) => {
+                        // nothing can send to this context any more, but its timers can still fire
+                        let deadline = deadline.unwrap();
+                        thread::sleep(deadline.saturating_duration_since(Instant::now()));
+                        continue;
+                    }
+                };
+                let QueueItem::Msg(msg) = msg else {
+                    // TODO: how do I indicate this branch is unlikely?
+                    match msg {
+                        QueueItem::Msg(_) => unreachable!(),
+                        QueueItem::AccessorDropped => {
+                            let block = unsafe { control_block_ptr.as_ref() };
+                            if block.unhandled_events.fetch_sub(1, Ordering::Relaxed) <= 1 {
+                                send_stop(&ctx.links);
+                                break;
+                            } else {
+                                continue;
+                            }
                         }
-                        break;
+                        // only sent once every context is past this loop
+                        QueueItem::Stopped => unreachable!(),
+                    }
+                };
+
+                supervision::run(&mut ctx, |ctx| msg.run(ctx));
+            }
+
+            // send local messages
+            ctx.handle_local();
+
+            // safety: this block is safe to use until we decrement block.unhandled_events
+            let block = unsafe { control_block_ptr.as_ref() };
+            // the event just handled is done; each message sent and timer armed is a new one. Messages
+            // are counted before they're sent, so their receivers can't release them first.
+            let new_events =
+                ctx.data.unsent_len as i64 + ctx.data.timers.len() as i64 - armed as i64;
+            if new_events > 1 {
+                block
+                    .unhandled_events
+                    .fetch_add(new_events as u32 - 1, Ordering::Relaxed);
+            }
+
+            // messages dropped by a full queue are done too
+            let dropped = flush_unsent(&mut ctx);
+
+            let done = (1 - new_events).max(0) as u32 + dropped;
+            if done > 0 && block.unhandled_events.fetch_sub(done, Ordering::Relaxed) <= done {
+                // TODO: is this fence necessary?
+                atomic::fence(Ordering::Acquire);
+                send_stop(&ctx.links);
+                break;
+            }
+        });
+        // the other contexts are still running
+        if !finished {
+            send_stop(&ctx.links);
+        }
+    }
+
+    phases.stopped.wait();
+    for offset in ctx.constructed.offsets().into_iter().rev() {
+        catch(&mut panicked, || {
+            supervision::run(&mut ctx, |ctx| {
+                ctx.run_hook(offset, |vtable| vtable.on_stop)
+            })
+        });
+    }
+    catch(&mut panicked, || ctx.handle_local());
+    flush_unsent(&mut ctx);
+    // handle what other contexts' actors send as they stop, up to the marker each sends after it
+    for link in ctx.links.iter() {
+        link.queue.send(QueueItem::Stopped).unwrap();
+    }
+    let mut stopping = ctx.links.len();
+    while stopping > 0 {
+        match ctx.rx.recv() {
+            Some(QueueItem::Msg(msg)) => {
+                catch(&mut panicked, || {
+                    supervision::run(&mut ctx, |ctx| msg.run(ctx));
+                    ctx.handle_local();
+                });
+                flush_unsent(&mut ctx);
+            }
+            Some(QueueItem::Stopped) => stopping -= 1,
+            Some(QueueItem::AccessorDropped) => {}
+            None => break,
+        }
+    }
+
+    // whatever's left may hold accessors, which in turn hold queues
+    ctx.rx.close();
+    drop(ctx);
+    // safety: this context has dropped everything that could refer to the block
+    unsafe { ControlBlock::release_holder(control_block_ptr) };
+    if let Some(panic) = panicked {
+        panic::resume_unwind(panic);
+    }
+    Ok(())
+}
+
+/// Runs `f`, keeping the first panic in `panicked`. Returns whether `f` returned.
+fn catch(panicked: &mut Option<Box<dyn Any + Send>>, f: impl FnOnce()) -> bool {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(()) => true,
+        Err(panic) => {
+            panicked.get_or_insert(panic);
+            false
+        }
+    }
+}
+
+/// Tells every other context to stop handling messages, without waiting on a full queue
+fn send_stop(links: &[ContextLink]) {
+    for link in links {
+        link.queue.interrupt();
+    }
+}
+
+/// Sends every message held for other contexts, returning how many were dropped by a full queue
+fn flush_unsent(ctx: &mut Context) -> u32 {
+    let mut dropped = 0;
+    if ctx.data.unsent_len > 0 {
+        ctx.data.unsent_len = 0;
+        let own = ctx.data.id.as_index();
+        for (i, unsent) in ctx.data.unsent_messages.iter_mut().enumerate() {
+            while let Some(msg) = unsent.recv(&mut ctx.data.slab) {
+                dropped += flush(&ctx.links[i - (i > own) as usize], msg);
+            }
+        }
+    }
+    dropped
+}
+
+/// Returns how many messages the receiving queue's backpressure policy dropped
+fn flush(link: &ContextLink, msg: Msg) -> u32 {
+    // `reject` can't be reported to whoever sent a deferred message, so it's only counted. The
+    // receiver can only be gone if it has finished shutting down, which drops the message anyway.
+    let (dropped, _) = link.queue.send_msg(msg);
+    dropped
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::{
+        iter,
+        sync::{mpsc, Mutex},
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::{
+        lookup::{Dependency, Key},
+        queue::remote::SendError,
+        register_actor, registry, Accessor, Actor, Handler, MainArgs, RuntimeHandle, SpawnError,
+        UniquelyNamed,
+    };
+
+    const MESSAGES: u32 = 100;
+
+    static RECEIVED: Mutex<Vec<(ContextId, u32)>> = Mutex::new(Vec::new());
+
+    struct OrderSender {
+        receivers: Vec<Key<OrderReceiver>>,
+    }
+
+    impl UniquelyNamed for OrderSender {
+        fn name() -> &'static str {
+            "OrderSender"
+        }
+    }
+
+    register_actor!(OrderSender);
+
+    impl Actor for OrderSender {
+        type Config = ();
+
+        fn init(mut args: InitArgs<Self>, _config: ()) -> anyhow::Result<Self> {
+            Ok(Self {
+                receivers: args.query::<OrderReceiver>().all_keys().collect(),
+            })
+        }
+
+        fn on_start(&mut self, args: &mut MainArgs) {
+            for i in 0..MESSAGES {
+                for &receiver in &self.receivers {
+                    // typed and closure messages share a queue, so they stay in order
+                    if i % 2 == 0 {
+                        args.send_msg(receiver, move |_, r| r.recv(i));
                     } else {
-                        continue;
+                        receiver.tell(args, i);
                     }
                 }
-                QueueItem::Stop => {
-                    break;
-                }
             }
+        }
+
+        fn dependencies() -> Vec<Dependency> {
+            vec![Dependency::any::<OrderReceiver>()]
+        }
+    }
+
+    struct OrderReceiver {
+        context: ContextId,
+    }
+
+    impl UniquelyNamed for OrderReceiver {
+        fn name() -> &'static str {
+            "OrderReceiver"
+        }
+    }
+
+    register_actor!(OrderReceiver);
+
+    impl OrderReceiver {
+        fn recv(&mut self, i: u32) {
+            RECEIVED.lock().unwrap().push((self.context, i));
+        }
+    }
+
+    impl Handler<u32> for OrderReceiver {
+        fn handle(&mut self, _args: &mut MainArgs, i: u32) {
+            self.recv(i);
+        }
+    }
+
+    impl Actor for OrderReceiver {
+        type Config = ();
+
+        fn init(args: InitArgs<Self>, _config: ()) -> anyhow::Result<Self> {
+            Ok(Self {
+                context: args.key().loc.context_id,
+            })
+        }
+    }
+
+    /// A config with actors of the given types on the given contexts, and contexts numbered from
+    /// 1 up to the highest one used
+    pub(crate) fn config(actors: &[(&str, u32)]) -> Config {
+        let contexts = actors.iter().map(|(_, context)| *context).max().unwrap();
+        let context = |id| config::Context {
+            id: ContextId::new(id).unwrap(),
+            thread_affinity: None,
+            queue: config::Queue::default(),
+            wait_strategy: config::WaitStrategy::default(),
+        };
+        let actor = |&(typename, context): &(&str, u32)| config::ActorConfig {
+            typename: typename.into(),
+            name: None,
+            config: serde_value::Value::Unit,
+            context: ContextId::new(context).unwrap(),
+            supervision: None,
         };
+        Config {
+            root: config::Scope {
+                name: None,
+                children: Default::default(),
+                actors: actors.iter().map(actor).collect(),
+                imported_scopes: Vec::new(),
+            },
+            contexts: (1..=contexts).map(context).collect(),
+            shutdown_on_signals: false,
+        }
+    }
 
-        msg(&mut ctx);
+    #[test]
+    fn messages_handled_in_send_order() {
+        let config = config(&[
+            ("OrderSender", 1),
+            ("OrderReceiver", 1),
+            ("OrderReceiver", 2),
+        ]);
+        try_run(config).unwrap();
 
-        // send local messages
-        while let Some(msg) = ctx.data.local_queue.recv() {
-            msg(&mut ctx);
+        let received = RECEIVED.lock().unwrap();
+        for context in [1, 2] {
+            let context = ContextId::new(context).unwrap();
+            let order: Vec<_> = received
+                .iter()
+                .filter(|(id, _)| *id == context)
+                .map(|(_, i)| *i)
+                .collect();
+            assert_eq!(order, (0..MESSAGES).collect::<Vec<_>>());
         }
+    }
 
-        // safety: this block is safe to use until we decrement block.unhandled_events
-        let block = unsafe { control_block_ptr.as_ref() };
-        match ctx.data.unsent_messages.len() {
-            0 => {
-                if block.unhandled_events.fetch_sub(1, Ordering::Relaxed) <= 1 {
-                    // TODO: is this fence necessary?
-                    atomic::fence(Ordering::Acquire);
-                    for link in ctx.links.iter() {
-                        link.queue.send(QueueItem::Stop).unwrap();
-                    }
-                    break;
-                }
-                continue;
+    static THREADS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    /// Records which thread it's constructed on
+    struct Pinned;
+
+    impl UniquelyNamed for Pinned {
+        fn name() -> &'static str {
+            "Pinned"
+        }
+    }
+
+    register_actor!(Pinned);
+
+    impl Actor for Pinned {
+        type Config = ();
+
+        fn init(_args: InitArgs<Self>, _config: ()) -> anyhow::Result<Self> {
+            let name = thread::current().name().unwrap_or_default().to_string();
+            THREADS.lock().unwrap().push(name);
+            Ok(Self)
+        }
+    }
+
+    #[test]
+    fn contexts_run_on_own_threads() {
+        let available = affinity::CpuSet::available().unwrap();
+        let cpu = (0..).find(|&cpu| available.contains(cpu)).unwrap();
+        let mut config = config(&[("Pinned", 1)]);
+        config.contexts[0].thread_affinity = Some(vec![cpu]);
+        try_run(config).unwrap();
+
+        assert_eq!(*THREADS.lock().unwrap(), ["dytor-ctx-1"]);
+        // pinning the context's thread left the calling thread as it was
+        let after = affinity::CpuSet::available().unwrap();
+        assert!((0..libc::CPU_SETSIZE as usize)
+            .all(|cpu| available.contains(cpu) == after.contains(cpu)));
+    }
+
+    struct Doomed;
+
+    impl UniquelyNamed for Doomed {
+        fn name() -> &'static str {
+            "Doomed"
+        }
+    }
+
+    register_actor!(Doomed);
+
+    impl Actor for Doomed {
+        type Config = ();
+
+        fn init(_args: InitArgs<Self>, _config: ()) -> anyhow::Result<Self> {
+            panic!("Doomed panicked in init");
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Doomed panicked in init")]
+    fn panic_during_startup() {
+        // the other context must not wait for the panicking one forever
+        let _ = try_run(config(&[("OrderReceiver", 1), ("Doomed", 2)]));
+    }
+
+    /// Fails its `init` when its config is 0
+    struct Refuser;
+
+    impl UniquelyNamed for Refuser {
+        fn name() -> &'static str {
+            "Refuser"
+        }
+    }
+
+    register_actor!(Refuser);
+
+    impl Actor for Refuser {
+        type Config = u32;
+
+        fn init(_args: InitArgs<Self>, config: u32) -> anyhow::Result<Self> {
+            anyhow::ensure!(config != 0, "refused");
+            Ok(Self)
+        }
+    }
+
+    #[test]
+    fn startup_errors() {
+        let err = try_run(config(&[("OrderReceiver", 1), ("Missing", 2)])).unwrap_err();
+        assert!(matches!(&err, Error::UnknownTypename(actor) if actor.index == 1));
+        assert_eq!(
+            err.to_string(),
+            "actor #1 (Missing) in scope <root> on context 2: typename is not registered"
+        );
+
+        // the config is `()`, not a number
+        let err = try_run(config(&[("Refuser", 1)])).unwrap_err();
+        assert!(
+            matches!(&err, Error::InvalidConfig { actor, .. } if &*actor.typename == "Refuser")
+        );
+        assert!(err
+            .to_string()
+            .starts_with("actor #0 (Refuser) in scope <root> on context 1: invalid config: "));
+
+        let mut refusing = config(&[("OrderReceiver", 1), ("Refuser", 1)]);
+        refusing.root.actors[1].config = serde_value::Value::U32(0);
+        let err = try_run(refusing).unwrap_err();
+        assert!(matches!(&err, Error::InitFailed { actor, .. } if actor.index == 1));
+        assert_eq!(
+            err.to_string(),
+            "actor #1 (Refuser) in scope <root> on context 1: init failed: refused"
+        );
+
+        let mut gap = config(&[("OrderReceiver", 1)]);
+        gap.contexts[0].id = ContextId::new(2).unwrap();
+        let err = try_run(gap).unwrap_err();
+        assert!(matches!(err, Error::InvalidContextIds));
+        assert_eq!(err.to_string(), "Contexts are not numbered as 1 ..= n");
+    }
+
+    /// Panics in the first message it handles
+    struct Crasher;
+
+    impl UniquelyNamed for Crasher {
+        fn name() -> &'static str {
+            "Crasher"
+        }
+    }
+
+    register_actor!(Crasher);
+
+    impl Actor for Crasher {
+        type Config = ();
+
+        fn init(mut args: InitArgs<Self>, _config: ()) -> anyhow::Result<Self> {
+            let key = args.key();
+            args.send_msg(key, |_, _| panic!("Crasher panicked in a handler"));
+            Ok(Self)
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Crasher panicked in a handler")]
+    fn panic_after_startup() {
+        // the other context must stop with the panicking one rather than wait for it
+        let _ = try_run(config(&[("OrderReceiver", 1), ("Crasher", 2)]));
+    }
+
+    static EVENTS: Mutex<Vec<(ContextId, &str)>> = Mutex::new(Vec::new());
+
+    /// Sends its peer on the other context a message as it starts and as it stops
+    struct LifecycleProbe {
+        context: ContextId,
+        peer: Key<dyn Recorder>,
+    }
+
+    impl UniquelyNamed for LifecycleProbe {
+        fn name() -> &'static str {
+            "LifecycleProbe"
+        }
+    }
+
+    register_actor!(LifecycleProbe { dyn Recorder });
+
+    // an actor can't hold a key to its own type, whose metadata would depend on itself
+    trait Recorder {
+        fn record(&self, event: &'static str);
+    }
+
+    impl Recorder for LifecycleProbe {
+        fn record(&self, event: &'static str) {
+            EVENTS.lock().unwrap().push((self.context, event));
+        }
+    }
+
+    impl Actor for LifecycleProbe {
+        type Config = ();
+
+        fn init(mut args: InitArgs<Self>, _config: ()) -> anyhow::Result<Self> {
+            let context = args.key().loc.context_id;
+            let peer = args
+                .query::<dyn Recorder>()
+                .all_keys()
+                .find(|key| key.loc.context_id != context)
+                .unwrap();
+            Ok(Self { context, peer })
+        }
+
+        fn dependencies() -> Vec<Dependency> {
+            vec![Dependency::any::<dyn Recorder>()]
+        }
+
+        fn on_start(&mut self, args: &mut MainArgs) {
+            self.record("start");
+            args.send_msg(self.peer, |_, peer| peer.record("message"));
+        }
+
+        fn on_stop(&mut self, args: &mut MainArgs) {
+            self.record("stop");
+            args.send_msg(self.peer, |_, peer| peer.record("final message"));
+        }
+    }
+
+    impl Drop for LifecycleProbe {
+        fn drop(&mut self) {
+            self.record("drop");
+        }
+    }
+
+    #[test]
+    fn lifecycle_hooks() {
+        try_run(config(&[("LifecycleProbe", 1), ("LifecycleProbe", 2)])).unwrap();
+
+        let events = EVENTS.lock().unwrap();
+        let position = |context, event| {
+            let context = ContextId::new(context).unwrap();
+            events.iter().position(|e| *e == (context, event)).unwrap()
+        };
+        for (context, peer) in [(1, 2), (2, 1)] {
+            // both started before either handles a message
+            assert!(position(peer, "start") < position(context, "message"));
+            assert!(position(context, "message") < position(context, "stop"));
+            // the peer's final message arrives before this one is dropped
+            assert!(position(peer, "stop") < position(context, "final message"));
+            assert!(position(context, "final message") < position(context, "drop"));
+        }
+    }
+
+    static LINGERING: Mutex<Vec<Accessor<Lingering>>> = Mutex::new(Vec::new());
+    static LINGERING_EVENTS: Mutex<Vec<&str>> = Mutex::new(Vec::new());
+
+    /// Keeps the system up by handing out an accessor to itself
+    struct Lingering {
+        _id: u32,
+    }
+
+    impl UniquelyNamed for Lingering {
+        fn name() -> &'static str {
+            "Lingering"
+        }
+    }
+
+    register_actor!(Lingering);
+
+    impl Actor for Lingering {
+        type Config = ();
+
+        fn init(args: InitArgs<Self>, _config: ()) -> anyhow::Result<Self> {
+            LINGERING.lock().unwrap().push(args.accessor());
+            Ok(Self { _id: 0 })
+        }
+
+        fn on_stop(&mut self, _args: &mut MainArgs) {
+            LINGERING_EVENTS.lock().unwrap().push("stop");
+        }
+    }
+
+    impl Drop for Lingering {
+        fn drop(&mut self) {
+            LINGERING_EVENTS.lock().unwrap().push("drop");
+        }
+    }
+
+    #[test]
+    fn shutdown_with_live_accessors() {
+        let handle = Runtime::start(config(&[("Lingering", 1)])).unwrap();
+        let accessors = mem::take(&mut *LINGERING.lock().unwrap());
+        handle.shutdown();
+        handle.join();
+        assert_eq!(*LINGERING_EVENTS.lock().unwrap(), ["stop", "drop"]);
+        assert_eq!(
+            accessors[0].send(|_, _| unreachable!()),
+            Err(SendError::Disconnected)
+        );
+        drop(accessors);
+
+        LINGERING_EVENTS.lock().unwrap().clear();
+        let mut config = config(&[("Lingering", 1), ("Lingering", 2)]);
+        config.root.actors[1].name = Some("second".into());
+        let handle = Runtime::start(config).unwrap();
+        let shutdown = handle.shutdown_handle();
+        assert_eq!(handle.accessors::<Lingering>().count(), 2);
+        assert_eq!(
+            handle.accessor_by_name::<Lingering>("first").err(),
+            Some(ByNameError::NotFound("first".into()))
+        );
+        handle
+            .accessor_by_name::<Lingering>("second")
+            .unwrap()
+            .send(|args, _| args.request_shutdown())
+            .unwrap();
+        handle.join();
+        assert!(shutdown.is_requested() && shutdown.is_finished());
+        assert_eq!(
+            *LINGERING_EVENTS.lock().unwrap(),
+            ["stop", "stop", "drop", "drop"]
+        );
+        LINGERING.lock().unwrap().clear();
+
+        let mut twins = super::test::config(&[("Lingering", 1), ("Lingering", 2)]);
+        for actor in &mut twins.root.actors {
+            actor.name = Some("twin".into());
+        }
+        let handle = Runtime::start(twins).unwrap();
+        assert_eq!(
+            handle.accessor_by_name::<Lingering>("twin").err(),
+            Some(ByNameError::Ambiguous("twin".into()))
+        );
+        handle.shutdown();
+        handle.join();
+        LINGERING.lock().unwrap().clear();
+    }
+
+    /// Fills the other context's queue as it starts
+    struct Flooder {
+        quitter: Key<Quitter>,
+    }
+
+    impl UniquelyNamed for Flooder {
+        fn name() -> &'static str {
+            "Flooder"
+        }
+    }
+
+    register_actor!(Flooder);
+
+    impl Actor for Flooder {
+        type Config = ();
+
+        fn init(mut args: InitArgs<Self>, _config: ()) -> anyhow::Result<Self> {
+            let quitter = args.query::<Quitter>().all_keys().next().unwrap();
+            Ok(Self { quitter })
+        }
+
+        fn on_start(&mut self, args: &mut MainArgs) {
+            for _ in 0..MESSAGES {
+                args.send_msg(self.quitter, |args, _| args.request_shutdown());
             }
-            1 => {
-                let (rx_id, msg) = ctx.data.unsent_messages.pop().unwrap();
-                let link = &mut ctx.links[rx_id.as_index() - (ctx.data.id > rx_id) as usize];
-                link.queue.send(QueueItem::Msg(msg)).unwrap();
-                continue;
+        }
+
+        fn dependencies() -> Vec<Dependency> {
+            vec![Dependency::any::<Quitter>()]
+        }
+    }
+
+    struct Quitter;
+
+    impl UniquelyNamed for Quitter {
+        fn name() -> &'static str {
+            "Quitter"
+        }
+    }
+
+    register_actor!(Quitter);
+
+    impl Actor for Quitter {
+        type Config = ();
+
+        fn init(_args: InitArgs<Self>, _config: ()) -> anyhow::Result<Self> {
+            Ok(Self)
+        }
+    }
+
+    #[test]
+    fn shutdown_with_full_queue() {
+        // the handler that requests the shutdown runs while its own queue is full
+        let mut config = config(&[("Quitter", 1), ("Flooder", 2)]);
+        config.contexts[0].queue = config::Queue::Bounded {
+            capacity: 1,
+            backpressure: config::Backpressure::DropNewest,
+        };
+        try_run(config).unwrap();
+    }
+
+    #[test]
+    fn rejected_across_contexts() {
+        // the flood is sent once `on_start` returns, so the rejections can only be counted
+        let mut config = config(&[("Quitter", 1), ("Flooder", 2)]);
+        config.contexts[0].queue = config::Queue::Bounded {
+            capacity: 1,
+            backpressure: config::Backpressure::Reject,
+        };
+        let handle = Runtime::start(config).unwrap();
+        let quitter = handle.accessors::<Quitter>().next().unwrap();
+        handle.join();
+        assert!(quitter.queue_metrics().rejected() > 0);
+        assert_eq!(quitter.queue_metrics().dropped_newest(), 0);
+    }
+
+    /// Registered once a system is running, as if by a library loaded then
+    struct Latecomer;
+
+    impl UniquelyNamed for Latecomer {
+        fn name() -> &'static str {
+            "Latecomer"
+        }
+    }
+
+    impl registry::ActorRegistered for Latecomer {}
+
+    impl Actor for Latecomer {
+        type Config = ();
+
+        fn init(_args: InitArgs<Self>, _config: ()) -> anyhow::Result<Self> {
+            Ok(Self)
+        }
+    }
+
+    static LATECOMER: registry::__private::ListNode = registry::__private::ListNode::new(|r| {
+        registry::__private::init_actor::<Latecomer>(r, Latecomer::dependencies, iter::empty())
+    });
+
+    #[test]
+    fn spawn_from_registry_at_startup() {
+        let spawn = |handle: &RuntimeHandle| {
+            let (tx, rx) = mpsc::channel();
+            let quitter = handle.accessors::<Quitter>().next().unwrap();
+            quitter
+                .send(move |args, _| {
+                    let spawned =
+                        args.spawn_by_name::<Latecomer>("Latecomer", serde_value::Value::Unit);
+                    tx.send(spawned.map(drop)).unwrap();
+                    args.request_shutdown();
+                })
+                .unwrap();
+            rx.recv().unwrap()
+        };
+
+        let running = Runtime::start(config(&[("Quitter", 1)])).unwrap();
+        registry::__private::init_node(&LATECOMER);
+        registry::sync().unwrap();
+        assert!(matches!(
+            spawn(&running),
+            Err(SpawnError::UnknownTypename(typename)) if typename == "Latecomer"
+        ));
+        running.join();
+
+        let started_later = Runtime::start(config(&[("Quitter", 1)])).unwrap();
+        spawn(&started_later).unwrap();
+        started_later.join();
+    }
+
+    static SESSIONS: Mutex<Vec<(u32, &str)>> = Mutex::new(Vec::new());
+
+    /// Spawns a session for every client that joins, and stops it when they leave
+    struct Lobby {
+        sessions: HashMap<u32, Key<Session>>,
+    }
+
+    impl UniquelyNamed for Lobby {
+        fn name() -> &'static str {
+            "Lobby"
+        }
+    }
+
+    register_actor!(Lobby);
+
+    struct Join(u32);
+    struct Leave(u32);
+
+    impl Handler<Join> for Lobby {
+        fn handle(&mut self, args: &mut MainArgs, Join(client): Join) {
+            let session = if client % 2 == 0 {
+                args.spawn::<Session>(client)
+            } else {
+                args.spawn_by_name("Session", serde_value::Value::U32(client))
+                    .unwrap()
+            };
+            // handled once the session has started
+            args.tell(session, Greet);
+            assert!(matches!(
+                args.spawn_by_name::<Session>("Lobby", serde_value::Value::Unit),
+                Err(SpawnError::WrongType { .. })
+            ));
+            self.sessions.insert(client, session);
+        }
+    }
+
+    impl Handler<Leave> for Lobby {
+        fn handle(&mut self, args: &mut MainArgs, Leave(client): Leave) {
+            let session = self.sessions.remove(&client).unwrap();
+            // sent before the stop, so it's still handled
+            args.tell(session, Greet);
+            args.stop(session);
+        }
+    }
+
+    impl Actor for Lobby {
+        type Config = ();
+
+        fn init(_args: InitArgs<Self>, _config: ()) -> anyhow::Result<Self> {
+            Ok(Self {
+                sessions: HashMap::new(),
+            })
+        }
+    }
+
+    struct Session {
+        client: u32,
+    }
+
+    impl UniquelyNamed for Session {
+        fn name() -> &'static str {
+            "Session"
+        }
+    }
+
+    register_actor!(Session);
+
+    struct Greet;
+
+    impl Handler<Greet> for Session {
+        fn handle(&mut self, _args: &mut MainArgs, _msg: Greet) {
+            SESSIONS.lock().unwrap().push((self.client, "greet"));
+        }
+    }
+
+    impl Actor for Session {
+        type Config = u32;
+
+        fn init(_args: InitArgs<Self>, client: u32) -> anyhow::Result<Self> {
+            SESSIONS.lock().unwrap().push((client, "init"));
+            Ok(Self { client })
+        }
+
+        fn on_start(&mut self, _args: &mut MainArgs) {
+            SESSIONS.lock().unwrap().push((self.client, "start"));
+        }
+
+        fn on_stop(&mut self, _args: &mut MainArgs) {
+            SESSIONS.lock().unwrap().push((self.client, "stop"));
+        }
+    }
+
+    impl Drop for Session {
+        fn drop(&mut self) {
+            SESSIONS.lock().unwrap().push((self.client, "drop"));
+        }
+    }
+
+    /// Spawns a `Child` and stops it again, over and over, then checks nothing was left behind
+    struct Churner {
+        me: Key<Churner>,
+        child: Option<Key<Child>>,
+    }
+
+    impl UniquelyNamed for Churner {
+        fn name() -> &'static str {
+            "Churner"
+        }
+    }
+
+    register_actor!(Churner);
+
+    struct Churn(u32);
+    struct Respawn(u32);
+    struct Check;
+
+    impl Handler<Churn> for Churner {
+        fn handle(&mut self, args: &mut MainArgs, Churn(rounds): Churn) {
+            if let Some(child) = self.child {
+                args.stop(child);
             }
-            n @ 2.. => {
-                block
-                    .unhandled_events
-                    .fetch_add(n as u32 - 1, Ordering::Relaxed);
+            if rounds == 0 {
+                args.tell(self.me, Check);
+            } else {
+                args.tell(self.me, Respawn(rounds));
             }
         }
+    }
+
+    impl Handler<Respawn> for Churner {
+        fn handle(&mut self, args: &mut MainArgs, Respawn(rounds): Respawn) {
+            let child = args.spawn::<Child>(());
+            if let Some(stopped) = self.child.replace(child) {
+                // the new child has the stopped one's slot, but not its key
+                assert_eq!(child.loc.offset.pos, stopped.loc.offset.pos);
+                args.tell(stopped, Ping);
+            }
+            args.tell(self.me, Churn(rounds - 1));
+        }
+    }
+
+    impl Handler<Check> for Churner {
+        fn handle(&mut self, args: &mut MainArgs, _msg: Check) {
+            assert!(args.context_data.stopped.is_empty());
+            let tree = args.context_data.live_tree.snapshot();
+            assert!(tree.spawned.is_empty() && tree.spawned_in.is_empty());
+            assert_eq!(tree.last_stopped.len(), 1);
+            *CHECKED.lock().unwrap() = true;
+        }
+    }
+
+    impl Actor for Churner {
+        type Config = ();
+
+        fn init(args: InitArgs<Self>, _config: ()) -> anyhow::Result<Self> {
+            Ok(Self {
+                me: args.key(),
+                child: None,
+            })
+        }
+
+        fn on_start(&mut self, args: &mut MainArgs) {
+            args.tell(self.me, Churn(100));
+        }
+    }
+
+    static CHECKED: Mutex<bool> = Mutex::new(false);
+    static PINGED: Mutex<u32> = Mutex::new(0);
+
+    struct Child;
+
+    impl UniquelyNamed for Child {
+        fn name() -> &'static str {
+            "Child"
+        }
+    }
+
+    register_actor!(Child);
+
+    struct Ping;
 
-        // group messages by thread, then send them out
-        // TODO: figure out how to do this faster. We probably can't assume input is nearly sorted.
-        // I imagine we may just want to have a preallocated buffer to sort with
-        ctx.data.unsent_messages.sort_by_key(|(id, _)| *id);
-        let ctx_id = ctx.data.id;
-        for (rx_id, msg) in ctx.data.unsent_messages.drain(..) {
-            let link = &mut ctx.links[rx_id.as_index() - (ctx_id > rx_id) as usize];
-            link.queue.send(QueueItem::Msg(msg)).unwrap();
+    impl Handler<Ping> for Child {
+        fn handle(&mut self, _args: &mut MainArgs, _msg: Ping) {
+            *PINGED.lock().unwrap() += 1;
+        }
+    }
+
+    impl Actor for Child {
+        type Config = ();
+
+        fn init(_args: InitArgs<Self>, _config: ()) -> anyhow::Result<Self> {
+            Ok(Self)
+        }
+    }
+
+    #[test]
+    fn spawn_and_stop_repeatedly() {
+        try_run(config(&[("Churner", 1)])).unwrap();
+        assert!(*CHECKED.lock().unwrap());
+        // every ping went to a child that had stopped
+        assert_eq!(*PINGED.lock().unwrap(), 0);
+    }
+
+    #[test]
+    fn spawned_actors() {
+        let handle = Runtime::start(config(&[("Lobby", 1)])).unwrap();
+        let lobby = handle.accessors::<Lobby>().next().unwrap();
+        for client in 1..=3 {
+            lobby.tell(Join(client)).unwrap();
+        }
+        lobby.tell(Leave(3)).unwrap();
+        drop(lobby);
+        while SESSIONS.lock().unwrap().len() < 11 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        // later lookups find the sessions still running
+        assert_eq!(handle.accessors::<Session>().count(), 2);
+        handle.join();
+
+        let sessions = SESSIONS.lock().unwrap();
+        let events = |client| -> Vec<_> {
+            sessions
+                .iter()
+                .filter(|(c, _)| *c == client)
+                .map(|(_, e)| *e)
+                .collect()
+        };
+        for client in 1..=2 {
+            assert_eq!(events(client), ["init", "start", "greet", "stop", "drop"]);
         }
+        assert_eq!(events(3), ["init", "start", "greet", "greet", "drop"]);
     }
 }
@@ -0,0 +1,244 @@
+use std::{collections::HashMap, sync::Arc};
+
+use crate::{
+    config::{ActorConfig, Scope},
+    context::ActorId,
+    error::ActorInfo,
+    lookup::{ActorTree, ScopeData, ScopeId},
+    ContextId, Error,
+};
+
+pub(crate) struct FlatScopes {
+    pub(crate) scopes: Vec<ScopeData>,
+    /// Indexed by `ActorId::as_index`
+    pub(crate) actors: Vec<(ScopeId, ActorConfig)>,
+}
+
+/// Flattens the scope tree, numbering actors depth-first.
+/// Children are visited in name order so that actor ids don't depend on hash order.
+pub(crate) fn flatten(root: Scope) -> Result<FlatScopes, Error> {
+    let mut res = FlatScopes {
+        scopes: Vec::new(),
+        actors: Vec::new(),
+    };
+    let mut imports = Vec::new();
+    add_scope(&mut res, &mut imports, root.name.clone(), None, root);
+
+    for (scope_id, names) in imports {
+        let resolved = names
+            .into_iter()
+            .map(|name| {
+                resolve(&res.scopes, scope_id, &name).ok_or_else(|| Error::UnknownScopeImport {
+                    scope: path(&res.scopes, scope_id),
+                    import: name,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        res.scopes[scope_id.as_index()].imports = resolved;
+    }
+
+    for i in 0..res.scopes.len() {
+        let mut visible = Vec::new();
+        let mut curr = Some(ScopeId(i as u32));
+        while let Some(id) = curr {
+            visible.push(id);
+            curr = res.scopes[id.as_index()].parent;
+        }
+        for &id in &res.scopes[i].imports {
+            if !visible.contains(&id) {
+                visible.push(id);
+            }
+        }
+        res.scopes[i].visible = visible.into_boxed_slice();
+    }
+
+    Ok(res)
+}
+
+fn add_scope(
+    res: &mut FlatScopes,
+    imports: &mut Vec<(ScopeId, Vec<Arc<str>>)>,
+    name: Option<Arc<str>>,
+    parent: Option<ScopeId>,
+    scope: Scope,
+) -> ScopeId {
+    let id = ScopeId(res.scopes.len() as u32);
+    res.scopes.push(ScopeData {
+        name,
+        parent,
+        children: HashMap::new(),
+        imports: Vec::new(),
+        actors: Vec::new(),
+        visible: Box::default(),
+    });
+
+    for cfg in scope.actors {
+        res.actors.push((id, cfg));
+        let actor_id = ActorId::new(res.actors.len() as u32).unwrap();
+        res.scopes[id.as_index()].actors.push(actor_id);
+    }
+    imports.push((id, scope.imported_scopes));
+
+    let mut children: Vec<_> = scope.children.into_iter().collect();
+    children.sort_by(|(a, _), (b, _)| a.cmp(b));
+    for (child_name, child) in children {
+        let child_id = add_scope(res, imports, Some(child_name.clone()), Some(id), child);
+        res.scopes[id.as_index()]
+            .children
+            .insert(child_name, child_id);
+    }
+
+    id
+}
+
+/// Resolves a `.`-separated scope path. The first component is looked up among the children of
+/// `from`, then among the children of each of its ancestors, nearest first.
+pub(crate) fn resolve(scopes: &[ScopeData], from: ScopeId, name: &str) -> Option<ScopeId> {
+    let mut components = name.split('.');
+    let first = components.next()?;
+
+    let mut curr = Some(from);
+    let mut found = None;
+    while let Some(id) = curr {
+        let scope = &scopes[id.as_index()];
+        if let Some(&child) = scope.children.get(first) {
+            found = Some(child);
+            break;
+        }
+        curr = scope.parent;
+    }
+
+    components.try_fold(found?, |id, component| {
+        scopes[id.as_index()].children.get(component).copied()
+    })
+}
+
+pub(crate) fn path(scopes: &[ScopeData], id: ScopeId) -> String {
+    let mut names = Vec::new();
+    let mut curr = Some(id);
+    while let Some(id) = curr {
+        let scope = &scopes[id.as_index()];
+        if scope.parent.is_some() || scope.name.is_some() {
+            names.push(scope.name.as_deref().unwrap_or_default());
+        }
+        curr = scope.parent;
+    }
+    if names.is_empty() {
+        return "<root>".into();
+    }
+    names.reverse();
+    names.join(".")
+}
+
+pub(crate) fn actor_info(
+    scopes: &[ScopeData],
+    scope: ScopeId,
+    id: ActorId,
+    typename: &Arc<str>,
+    context: ContextId,
+) -> ActorInfo {
+    ActorInfo {
+        index: scopes[scope.as_index()]
+            .actors
+            .iter()
+            .position(|&actor| actor == id)
+            .unwrap(),
+        typename: typename.clone(),
+        scope: path(scopes, scope),
+        context,
+    }
+}
+
+pub(crate) fn actor_info_in_tree(tree: &ActorTree, id: ActorId) -> ActorInfo {
+    let actor = tree.actor(id);
+    ActorInfo {
+        index: tree.index_in_scope(id),
+        typename: Arc::from((actor.vtable.name)()),
+        scope: path(&tree.scopes, actor.scope),
+        context: actor.loc.context_id,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn scope(actors: &[&str], imports: &[&str], children: Vec<(&str, Scope)>) -> Scope {
+        Scope {
+            name: None,
+            children: children
+                .into_iter()
+                .map(|(name, s)| (name.into(), s))
+                .collect(),
+            actors: actors
+                .iter()
+                .map(|typename| ActorConfig {
+                    typename: (*typename).into(),
+                    name: None,
+                    config: serde_value::Value::Unit,
+                    context: ContextId::new(1).unwrap(),
+                    supervision: None,
+                })
+                .collect(),
+            imported_scopes: imports.iter().map(|&name| name.into()).collect(),
+        }
+    }
+
+    fn visible_actors(flat: &FlatScopes, actor: u32) -> Vec<String> {
+        let (scope, _) = &flat.actors[actor as usize];
+        let mut res: Vec<_> = flat.scopes[scope.as_index()]
+            .visible
+            .iter()
+            .flat_map(|s| &flat.scopes[s.as_index()].actors)
+            .map(|id| flat.actors[id.as_index()].1.typename.to_string())
+            .collect();
+        res.sort();
+        res
+    }
+
+    #[test]
+    fn ancestors_visible() {
+        let flat = flatten(scope(
+            &["Root"],
+            &[],
+            vec![
+                (
+                    "a",
+                    scope(&["A"], &[], vec![("b", scope(&["B"], &[], vec![]))]),
+                ),
+                ("c", scope(&["C"], &[], vec![])),
+            ],
+        ))
+        .unwrap();
+        assert_eq!(visible_actors(&flat, 0), ["Root"]);
+        assert_eq!(visible_actors(&flat, 1), ["A", "Root"]);
+        assert_eq!(visible_actors(&flat, 2), ["A", "B", "Root"]);
+        assert_eq!(visible_actors(&flat, 3), ["C", "Root"]);
+    }
+
+    #[test]
+    fn imports() {
+        let flat = flatten(scope(
+            &[],
+            &["a.b"],
+            vec![
+                (
+                    "a",
+                    scope(&["A"], &[], vec![("b", scope(&["B"], &["c"], vec![]))]),
+                ),
+                ("c", scope(&["C"], &[], vec![])),
+            ],
+        ))
+        .unwrap();
+        assert_eq!(visible_actors(&flat, 0), ["A"]);
+        assert_eq!(visible_actors(&flat, 1), ["A", "B", "C"]);
+        assert_eq!(visible_actors(&flat, 2), ["C"]);
+        assert_eq!(path(&flat.scopes, flat.actors[1].0), "a.b");
+    }
+
+    #[test]
+    fn unknown_import() {
+        let res = flatten(scope(&[], &["missing"], vec![]));
+        assert!(matches!(res, Err(Error::UnknownScopeImport { .. })));
+    }
+}
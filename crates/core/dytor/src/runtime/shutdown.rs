@@ -0,0 +1,80 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Condvar, Mutex,
+};
+
+use crate::context::MsgTx;
+
+/// Shuts a running system down from any thread. Clones refer to the same system.
+///
+/// The system also shuts down by itself once nothing is left to happen: no messages in flight,
+/// no armed timers and no live [`crate::Accessor`]s. Shutting down doesn't wait for that, so it
+/// works while an accessor is held elsewhere, e.g. by a background task.
+///
+/// # Drain order
+///
+/// 1. Each context finishes the handler it's running and stops handling messages. Timers that
+///    haven't fired are dropped; messages still queued are handled in step 3.
+/// 2. Once every context has stopped, each runs [`crate::Actor::on_stop`] for its actors in
+///    reverse construction order.
+/// 3. Each context handles what's still in its queue, including messages sent from `on_stop`,
+///    until every other context has finished its own `on_stop` hooks.
+/// 4. Each context drops its actors in reverse construction order, along with any messages still
+///    queued or sent to it later, without running them.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    state: Arc<State>,
+}
+
+struct State {
+    requested: AtomicBool,
+    queues: Box<[MsgTx]>,
+    finished: Mutex<bool>,
+    finished_cond: Condvar,
+}
+
+impl ShutdownHandle {
+    pub(crate) fn new(queues: Box<[MsgTx]>) -> Self {
+        Self {
+            state: Arc::new(State {
+                requested: AtomicBool::new(false),
+                queues,
+                finished: Mutex::new(false),
+                finished_cond: Condvar::new(),
+            }),
+        }
+    }
+
+    /// Asks every context to stop. Returns immediately, without waiting for room in a full
+    /// queue, so it's safe to call from a handler; use [`Self::wait`] to block until the system
+    /// has shut down. Only the first call has any effect.
+    pub fn shutdown(&self) {
+        if self.state.requested.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        for queue in self.state.queues.iter() {
+            queue.interrupt();
+        }
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.state.requested.load(Ordering::Relaxed)
+    }
+
+    /// Blocks until every context has stopped and dropped its actors
+    pub fn wait(&self) {
+        let mut finished = self.state.finished.lock().unwrap();
+        while !*finished {
+            finished = self.state.finished_cond.wait(finished).unwrap();
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        *self.state.finished.lock().unwrap()
+    }
+
+    pub(crate) fn set_finished(&self) {
+        *self.state.finished.lock().unwrap() = true;
+        self.state.finished_cond.notify_all();
+    }
+}
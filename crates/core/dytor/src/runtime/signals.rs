@@ -0,0 +1,180 @@
+//! Shuts systems down on SIGINT or SIGTERM. The handler only writes to a pipe; a watcher thread
+//! reads it and does the actual shutdown, since almost nothing is safe to call from a handler.
+
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicI32, Ordering},
+        Mutex, OnceLock,
+    },
+    thread,
+};
+
+use super::shutdown::ShutdownHandle;
+
+static WATCHED: Mutex<Watched> = Mutex::new(Watched {
+    handles: Vec::new(),
+    previous: None,
+});
+static PIPE_WRITE: AtomicI32 = AtomicI32::new(-1);
+
+struct Watched {
+    handles: Vec<ShutdownHandle>,
+    /// The actions for SIGINT and SIGTERM that ours replaced, while ours are installed
+    previous: Option<[libc::sigaction; 2]>,
+}
+
+// safety: a sigaction is plain data; its handler pointers aren't dereferenced here
+unsafe impl Send for Watched {}
+
+const SIGNALS: [libc::c_int; 2] = [libc::SIGINT, libc::SIGTERM];
+
+/// Shuts `handle`'s system down on the next SIGINT or SIGTERM. The handlers are installed while
+/// a watched system is running. After a signal, or once every watched system has finished, the
+/// previous ones are put back, so e.g. a second Ctrl-C kills the process as it would have.
+pub(crate) fn watch(handle: ShutdownHandle) -> io::Result<()> {
+    static WATCHER: OnceLock<Result<(), io::ErrorKind>> = OnceLock::new();
+    (*WATCHER.get_or_init(|| start_watcher().map_err(|e| e.kind())))?;
+
+    let mut watched = WATCHED.lock().unwrap();
+    watched.handles.retain(|handle| !handle.is_finished());
+    if watched.previous.is_none() {
+        watched.previous = Some(install()?);
+    }
+    watched.handles.push(handle);
+    Ok(())
+}
+
+/// Forgets systems that have finished, putting the previous handlers back if none are left
+pub(crate) fn unwatch_finished() {
+    let mut watched = WATCHED.lock().unwrap();
+    watched.handles.retain(|handle| !handle.is_finished());
+    if watched.handles.is_empty() {
+        if let Some(previous) = watched.previous.take() {
+            restore(&previous);
+        }
+    }
+}
+
+fn start_watcher() -> io::Result<()> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let [read, write] = fds;
+    // the handler mustn't block on a full pipe, but the watcher does block on an empty one
+    if unsafe { libc::fcntl(write, libc::F_SETFL, libc::O_NONBLOCK) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    PIPE_WRITE.store(write, Ordering::Relaxed);
+
+    thread::Builder::new()
+        .name("dytor-signals".into())
+        .spawn(move || loop {
+            let mut buf = [0u8; 1];
+            let res = unsafe { libc::read(read, buf.as_mut_ptr().cast(), 1) };
+            if res < 0 && io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            if res <= 0 {
+                return;
+            }
+            let mut watched = WATCHED.lock().unwrap();
+            for handle in watched.handles.drain(..) {
+                handle.shutdown();
+            }
+            if let Some(previous) = watched.previous.take() {
+                restore(&previous);
+            }
+        })?;
+    Ok(())
+}
+
+/// Installs our handlers, returning the actions they replace
+fn install() -> io::Result<[libc::sigaction; 2]> {
+    // Safety: sigaction is plain data, and the handler is async-signal-safe
+    let mut previous: [libc::sigaction; 2] = unsafe { std::mem::zeroed() };
+    for (i, signal) in SIGNALS.into_iter().enumerate() {
+        let res = unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(signal, &action, &mut previous[i])
+        };
+        if res != 0 {
+            let e = io::Error::last_os_error();
+            restore(&previous[..i]);
+            return Err(e);
+        }
+    }
+    Ok(previous)
+}
+
+fn restore(previous: &[libc::sigaction]) {
+    for (&signal, action) in SIGNALS.iter().zip(previous) {
+        // Safety: `action` was returned by sigaction for this signal
+        unsafe { libc::sigaction(signal, action, std::ptr::null_mut()) };
+    }
+}
+
+extern "C" fn on_signal(_: libc::c_int) {
+    let fd = PIPE_WRITE.load(Ordering::Relaxed);
+    // the interrupted code may be about to read errno, which a failed write would overwrite
+    let errno = unsafe { *libc::__errno_location() };
+    // a full pipe already has a shutdown pending
+    unsafe { libc::write(fd, [1u8].as_ptr().cast(), 1) };
+    unsafe { *libc::__errno_location() = errno };
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, process::Command};
+
+    use super::*;
+    use crate::runtime::{test::config, Runtime};
+
+    /// Set in the child process the test runs itself in, since the signal goes to the whole process
+    const CHILD: &str = "DYTOR_SIGNALS_TEST_CHILD";
+
+    fn handler(signal: libc::c_int) -> libc::sighandler_t {
+        let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+        assert_eq!(
+            unsafe { libc::sigaction(signal, std::ptr::null(), &mut action) },
+            0
+        );
+        action.sa_sigaction
+    }
+
+    #[test]
+    fn sigterm_shuts_down() {
+        if env::var_os(CHILD).is_none() {
+            let status = Command::new(env::current_exe().unwrap())
+                .args(["--exact", "runtime::signals::test::sigterm_shuts_down"])
+                .env(CHILD, "1")
+                .status()
+                .unwrap();
+            assert!(status.success(), "{status}");
+            return;
+        }
+
+        let mut config = config(&[("Quitter", 1)]);
+        config.shutdown_on_signals = true;
+        // the handle keeps the system up until the signal shuts it down
+        let handle = Runtime::start(config).unwrap();
+        assert_eq!(
+            handler(libc::SIGTERM),
+            on_signal as extern "C" fn(libc::c_int) as usize
+        );
+        let write = PIPE_WRITE.load(Ordering::Relaxed);
+        assert_ne!(
+            unsafe { libc::fcntl(write, libc::F_GETFL) } & libc::O_NONBLOCK,
+            0
+        );
+
+        unsafe { libc::raise(libc::SIGTERM) };
+        handle.join();
+        assert_eq!(handler(libc::SIGTERM), libc::SIG_DFL);
+        assert_eq!(handler(libc::SIGINT), libc::SIG_DFL);
+    }
+}
@@ -0,0 +1,193 @@
+//! Actors added while the system is running, e.g. one per client session. A spawned actor lives in
+//! an overflow chunk of its context's arena, since the arena itself is sized for the config's
+//! actors, and is added to the actor tree so lookups made afterwards find it, broadcast groups
+//! included.
+//!
+//! Spawned actors are unnamed, unsupervised, and declared in the same scope as the actor that
+//! spawned them. They're stopped and dropped along with the rest of their context, unless
+//! [`MainArgs::stop`] stops them first, which makes their room in the arena available to later
+//! spawns.
+
+use std::{
+    any::{Any, TypeId},
+    fmt, mem,
+    ptr::Pointee,
+};
+
+use super::{construct_running, supervision, ActorConstructorInfo};
+use crate::{
+    arena::Offset,
+    context::{Context, MainArgs},
+    dead_letter::SpawnFailure,
+    lookup::{ActorTree, Key, Loc, Lookup},
+    object::VTable,
+    Actor,
+};
+
+#[derive(Debug)]
+pub enum SpawnError {
+    UnknownTypename(String),
+    InvalidConfig(anyhow::Error),
+    /// The actor type is neither the requested type nor registered with the requested trait
+    WrongType {
+        typename: String,
+        expected: &'static str,
+    },
+}
+
+impl fmt::Display for SpawnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpawnError::UnknownTypename(typename) => {
+                write!(f, "Cannot spawn unknown actor type {typename}")
+            }
+            SpawnError::InvalidConfig(source) => write!(f, "Invalid config: {source:#}"),
+            SpawnError::WrongType { typename, expected } => {
+                write!(f, "{typename} cannot be looked up as {expected}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SpawnError {}
+
+impl MainArgs<'_> {
+    /// Adds a `T` to this context. It's constructed once the current handler returns, then its
+    /// `on_start` runs, before this context handles anything else, so messages sent to the key
+    /// right away are handled by the new actor.
+    ///
+    /// If its `init` fails or panics, the actor never starts. It's taken out of the actor tree
+    /// again, the dead letter sink is told why, and messages to it are reported as dead letters;
+    /// see [`crate::dead_letter`].
+    pub fn spawn<T: Actor>(&mut self, config: T::Config) -> Key<T>
+    where
+        T::Config: 'static,
+    {
+        let vtable = self
+            .context_data
+            .registry
+            .actor_types
+            .get(&TypeId::of::<T>())
+            .unwrap_or_else(|| panic!("{} isn't registered", T::name()));
+        let loc = self.spawn_actor(vtable, Box::new(config));
+        Key { loc, meta: () }
+    }
+
+    /// Like [`Self::spawn`], for an actor type only known by its typename, e.g. one from a
+    /// library loaded before the system started; see [`crate::plugins::load`]. `T` is the
+    /// actor's type or a trait it's registered with.
+    pub fn spawn_by_name<T: ?Sized + 'static>(
+        &mut self,
+        typename: &str,
+        config: serde_value::Value,
+    ) -> Result<Key<T>, SpawnError>
+    where
+        ActorTree: Lookup<T, <T as Pointee>::Metadata>,
+    {
+        let Some((_, vtable)) = self.context_data.registry.by_name(typename) else {
+            return Err(SpawnError::UnknownTypename(typename.into()));
+        };
+        let Some(meta) = <ActorTree as Lookup<T, _>>::meta(vtable) else {
+            return Err(SpawnError::WrongType {
+                typename: typename.into(),
+                expected: std::any::type_name::<T>(),
+            });
+        };
+        let config = (vtable.deserialize_yaml_value)(config).map_err(SpawnError::InvalidConfig)?;
+        let loc = self.spawn_actor(vtable, config);
+        Ok(Key { loc, meta })
+    }
+
+    /// Stops the actor behind `key` once it has handled the messages sent to it before this,
+    /// wherever it is. It's dropped without its `on_stop` running, like an actor stopped by its
+    /// [`crate::config::Supervision`], and taken out of the actor tree, so only lookups made
+    /// earlier still find it. Messages sent to it from then on are reported as dead letters; see
+    /// [`crate::dead_letter`].
+    ///
+    /// Any actor can be stopped this way, but only a spawned actor's room in its context's arena is
+    /// reused.
+    pub fn stop<T: ?Sized>(&mut self, key: Key<T>)
+    where
+        <T as Pointee>::Metadata: 'static,
+    {
+        self.send_msg(key, |args, _| args.context_data.stopping = true);
+    }
+
+    fn spawn_actor(&mut self, vtable: &'static VTable, config: Box<dyn Any + Send>) -> Loc {
+        let data = &mut *self.context_data;
+        let offset = self.arena.allocate(vtable.layout());
+        let loc = Loc {
+            context_id: data.id,
+            offset,
+        };
+        // the spawner is whichever actor is being called, so hooks can spawn too
+        let spawner = Loc {
+            context_id: data.id,
+            offset: self.actor,
+        };
+        let scope = data.live_tree.snapshot().actor_at(spawner).unwrap().scope;
+        let id = data.live_tree.insert(vtable, loc, scope);
+        // until it's constructed
+        data.stopped.insert(offset);
+        data.spawned.push(ActorConstructorInfo {
+            id,
+            offset,
+            vtable,
+            config,
+            supervision: None,
+            restart_config: None,
+        });
+        loc
+    }
+}
+
+impl Context {
+    /// Constructs the actors spawned by the handler that just returned, then runs their
+    /// `on_start`. One that fails to construct is taken out again, and reported to the
+    /// [`crate::dead_letter::DeadLetterSink`] if there is one.
+    pub(crate) fn start_spawned(&mut self) {
+        for actor in mem::take(&mut self.data.spawned) {
+            let (id, offset, vtable) = (actor.id, actor.offset, actor.vtable);
+            match construct_running(self, actor) {
+                Ok(()) => self.run_hook(offset, |vtable| vtable.on_start),
+                Err(reason) => {
+                    // e.g. an `ask_timeout` from `init` armed a timer for it
+                    self.data.timers.cancel_target(offset);
+                    self.remove_actor(offset, vtable);
+                    let failure = SpawnFailure {
+                        typename: (vtable.name)(),
+                        actor: id,
+                        reason,
+                    };
+                    let tree = self.data.live_tree.snapshot();
+                    if let Some(sink) = Context::dead_letter_sink(&tree) {
+                        self.data
+                            .send_msg(sink, move |args, sink| sink.spawn_failed(args, failure));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Stops the actor that handled a [`MainArgs::stop`] message, now that it has returned
+    pub(crate) fn stop_actor(&mut self, offset: Offset) {
+        self.data.stopping = false;
+        let vtable = supervision::vtable(self, offset);
+        supervision::stop(self, offset);
+        self.remove_actor(offset, vtable);
+    }
+
+    /// Takes a stopped actor, or one that was never constructed, out of the actor tree and frees
+    /// its slot in the arena
+    fn remove_actor(&mut self, offset: Offset, vtable: &'static VTable) {
+        self.data.live_tree.remove(Loc {
+            context_id: self.data.id,
+            offset,
+        });
+        if self.arena.free(offset, vtable.layout()) {
+            // the arena knows its offset is stale from now on
+            self.constructed.remove(offset);
+            self.data.stopped.remove(&offset);
+        }
+    }
+}
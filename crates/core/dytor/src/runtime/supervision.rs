@@ -0,0 +1,420 @@
+//! Catches panics in the handlers of actors with a [`Supervision`] policy, and applies it.
+//! Contexts without supervised actors don't catch anything.
+//!
+//! [`Supervision::Escalate`] shuts the whole system down, not only the failing actor's context:
+//! contexts only shut down together, once each has stopped handling messages.
+
+use std::{
+    collections::HashMap,
+    panic::{self, AssertUnwindSafe},
+};
+
+use super::{construct_running, ActorConstructorInfo};
+use crate::{
+    arena::Offset,
+    config::Supervision,
+    context::{ActorId, Context},
+    object::VTable,
+};
+
+/// The policies of a context's supervised actors
+pub(crate) struct Supervisor {
+    pub(crate) actors: HashMap<Offset, Supervised>,
+}
+
+pub(crate) struct Supervised {
+    pub(crate) id: ActorId,
+    pub(crate) policy: Supervision,
+    /// Only kept for [`Supervision::Restart`]
+    pub(crate) config: Option<serde_value::Value>,
+}
+
+/// Runs `f`, which handles one message, timer or hook. If it panics while calling a supervised
+/// actor, the panic is caught and the actor's policy applied; otherwise the panic carries on.
+pub(crate) fn run(ctx: &mut Context, f: impl FnOnce(&mut Context)) {
+    if ctx.supervisor.is_none() {
+        return f(ctx);
+    }
+    ctx.data.current_actor = None;
+    let Err(panic) = panic::catch_unwind(AssertUnwindSafe(|| f(ctx))) else {
+        return;
+    };
+    let supervised = ctx.data.current_actor.and_then(|offset| {
+        let policy = ctx.supervisor.as_ref()?.actors.get(&offset)?.policy;
+        Some((offset, policy))
+    });
+    let Some((offset, policy)) = supervised else {
+        panic::resume_unwind(panic);
+    };
+    match policy {
+        Supervision::Restart => restart(ctx, offset),
+        Supervision::Stop => stop(ctx, offset),
+        Supervision::Escalate => {
+            stop(ctx, offset);
+            ctx.data.shutdown.shutdown();
+        }
+    }
+}
+
+/// Drops the actor and stops calling it, cancelling its timers
+pub(crate) fn stop(ctx: &mut Context, offset: Offset) {
+    let vtable = vtable(ctx, offset);
+    ctx.data.stopped.insert(offset);
+    ctx.data.timers.cancel_target(offset);
+    let ptr = ctx.arena.offset(offset);
+    // a panic while dropping would otherwise reach the context a second time
+    let _ = panic::catch_unwind(|| unsafe { (vtable.drop)(ptr) });
+}
+
+/// Drops the actor, then constructs it again in place and runs its `on_start`
+fn restart(ctx: &mut Context, offset: Offset) {
+    stop(ctx, offset);
+    let vtable = vtable(ctx, offset);
+    let actor = &ctx.supervisor.as_ref().unwrap().actors[&offset];
+    let (id, policy) = (actor.id, actor.policy);
+    let Ok(config) = (vtable.deserialize_yaml_value)(actor.config.clone().unwrap()) else {
+        return;
+    };
+    let actor = ActorConstructorInfo {
+        id,
+        offset,
+        vtable,
+        config,
+        supervision: Some(policy),
+        restart_config: None,
+    };
+    if construct_running(ctx, actor).is_err() {
+        return;
+    }
+
+    let started = panic::catch_unwind(AssertUnwindSafe(|| {
+        ctx.run_hook(offset, |vtable| vtable.on_start)
+    }));
+    // restarting again would likely panic again
+    if started.is_err() {
+        stop(ctx, offset);
+    }
+}
+
+pub(crate) fn vtable(ctx: &Context, offset: Offset) -> &'static VTable {
+    ctx.constructed.vtable(offset).unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use crate::{
+        config::Supervision,
+        lookup::{AcyclicLocalKey, BroadcastGroup, Dependency, Key},
+        register_actor,
+        runtime::{test::config, try_run, Runtime},
+        Actor, Handler, InitArgs, MainArgs, UniquelyNamed,
+    };
+
+    static EVENTS: Mutex<Vec<(String, &str)>> = Mutex::new(Vec::new());
+
+    fn record(label: &str, event: &'static str) {
+        EVENTS.lock().unwrap().push((label.into(), event));
+    }
+
+    fn events(label: &str) -> Vec<&'static str> {
+        let events = EVENTS.lock().unwrap();
+        events
+            .iter()
+            .filter(|(l, _)| l == label)
+            .map(|(_, e)| *e)
+            .collect()
+    }
+
+    /// Tells itself to panic when it's first constructed, then to count
+    struct Fragile {
+        label: String,
+    }
+
+    impl UniquelyNamed for Fragile {
+        fn name() -> &'static str {
+            "Fragile"
+        }
+    }
+
+    register_actor!(Fragile);
+
+    enum Command {
+        Panic,
+        Count,
+    }
+
+    impl Handler<Command> for Fragile {
+        fn handle(&mut self, _args: &mut MainArgs, cmd: Command) {
+            match cmd {
+                Command::Panic => {
+                    record(&self.label, "panic");
+                    panic!("{} panicked on purpose", self.label);
+                }
+                Command::Count => record(&self.label, "count"),
+            }
+        }
+    }
+
+    impl Actor for Fragile {
+        type Config = String;
+
+        fn init(mut args: InitArgs<Self>, label: String) -> anyhow::Result<Self> {
+            let restarted = !events(&label).is_empty();
+            record(&label, "init");
+            if !restarted {
+                let key = args.key();
+                args.tell(key, Command::Panic);
+                args.tell(key, Command::Count);
+            }
+            Ok(Self { label })
+        }
+    }
+
+    impl Drop for Fragile {
+        fn drop(&mut self) {
+            record(&self.label, "drop");
+        }
+    }
+
+    #[test]
+    fn policies() {
+        let policies = [
+            ("restart", Supervision::Restart),
+            ("stop", Supervision::Stop),
+            ("escalate", Supervision::Escalate),
+        ];
+        let mut config = config(&[("Fragile", 1), ("Fragile", 1), ("Fragile", 2)]);
+        for (actor, (label, policy)) in config.root.actors.iter_mut().zip(policies) {
+            actor.config = serde_value::Value::String(label.into());
+            actor.supervision = Some(policy);
+        }
+        // the handle keeps the system up until the escalating actor shuts it down
+        let handle = Runtime::start(config).unwrap();
+        handle.shutdown_handle().wait();
+        handle.join();
+
+        assert_eq!(
+            events("restart"),
+            ["init", "panic", "drop", "init", "count", "drop"]
+        );
+        // its count is dropped along with the actor
+        assert_eq!(events("stop"), ["init", "panic", "drop"]);
+        assert_eq!(events("escalate"), ["init", "panic", "drop"]);
+    }
+
+    /// Calls into its local dependency, which panics, then carries on
+    struct Caller {
+        me: Key<Caller>,
+        callee: AcyclicLocalKey<Callee>,
+    }
+
+    impl UniquelyNamed for Caller {
+        fn name() -> &'static str {
+            "Caller"
+        }
+    }
+
+    register_actor!(Caller);
+
+    impl Actor for Caller {
+        type Config = ();
+
+        fn init(mut args: InitArgs<Self>, _config: ()) -> anyhow::Result<Self> {
+            Ok(Self {
+                me: args.key(),
+                callee: args.query().acyclic_local_key(),
+            })
+        }
+
+        fn on_start(&mut self, args: &mut MainArgs) {
+            args.send_msg(self.me, |args, caller| {
+                caller
+                    .callee
+                    .call(args, |_, _| panic!("callee panicked on purpose"))
+            });
+            args.send_msg(self.me, |_, _| record("caller", "alive"));
+        }
+
+        fn dependencies() -> Vec<Dependency> {
+            vec![Dependency::acyclic_local::<Callee>()]
+        }
+    }
+
+    struct Callee;
+
+    impl UniquelyNamed for Callee {
+        fn name() -> &'static str {
+            "Callee"
+        }
+    }
+
+    register_actor!(Callee);
+
+    impl Actor for Callee {
+        type Config = ();
+
+        fn init(_args: InitArgs<Self>, _config: ()) -> anyhow::Result<Self> {
+            Ok(Self)
+        }
+    }
+
+    impl Drop for Callee {
+        fn drop(&mut self) {
+            record("callee", "drop");
+        }
+    }
+
+    #[test]
+    fn panic_in_local_dependency_blamed_on_it() {
+        // the caller isn't supervised, so its context would go down if the panic were its own
+        let mut config = config(&[("Caller", 1), ("Callee", 1)]);
+        config.root.actors[1].supervision = Some(Supervision::Stop);
+        try_run(config).unwrap();
+        assert_eq!(events("callee"), ["drop"]);
+        assert_eq!(events("caller"), ["alive"]);
+    }
+
+    /// Depends on a `Fragile`, and panics once it has started for the first time
+    struct Dependent {
+        label: String,
+        me: Key<Dependent>,
+        _fragile: AcyclicLocalKey<Fragile>,
+    }
+
+    impl UniquelyNamed for Dependent {
+        fn name() -> &'static str {
+            "Dependent"
+        }
+    }
+
+    register_actor!(Dependent);
+
+    impl Actor for Dependent {
+        type Config = String;
+
+        fn init(mut args: InitArgs<Self>, label: String) -> anyhow::Result<Self> {
+            record(&label, "init");
+            Ok(Self {
+                label,
+                me: args.key(),
+                _fragile: args.query().acyclic_local_key(),
+            })
+        }
+
+        fn on_start(&mut self, args: &mut MainArgs) {
+            let restarted = events(&self.label).contains(&"panic");
+            record(&self.label, "start");
+            if !restarted {
+                args.send_msg(self.me, |_, dependent: &mut Self| {
+                    record(&dependent.label, "panic");
+                    panic!("{} panicked on purpose", dependent.label);
+                });
+            }
+        }
+
+        fn dependencies() -> Vec<Dependency> {
+            vec![Dependency::acyclic_local::<Fragile>()]
+        }
+    }
+
+    impl Drop for Dependent {
+        fn drop(&mut self) {
+            record(&self.label, "drop");
+        }
+    }
+
+    #[test]
+    fn restart_without_local_dependency() {
+        let mut config = config(&[("Dependent", 1), ("Fragile", 1)]);
+        config.root.actors[0].config = serde_value::Value::String("dependent".into());
+        config.root.actors[0].supervision = Some(Supervision::Restart);
+        config.root.actors[1].config = serde_value::Value::String("depended on".into());
+        config.root.actors[1].supervision = Some(Supervision::Stop);
+        try_run(config).unwrap();
+        assert_eq!(events("depended on"), ["init", "panic", "drop"]);
+        // the fragile actor stopped before the dependent panicked, so it's dropped, not started
+        assert_eq!(
+            events("dependent"),
+            ["init", "start", "panic", "drop", "init", "drop"]
+        );
+    }
+
+    /// Broadcasts to every listener as it starts
+    struct Broadcaster {
+        listeners: BroadcastGroup<Listener>,
+    }
+
+    impl UniquelyNamed for Broadcaster {
+        fn name() -> &'static str {
+            "Broadcaster"
+        }
+    }
+
+    register_actor!(Broadcaster);
+
+    impl Actor for Broadcaster {
+        type Config = ();
+
+        fn init(mut args: InitArgs<Self>, _config: ()) -> anyhow::Result<Self> {
+            Ok(Self {
+                listeners: args.query().broadcast_group(),
+            })
+        }
+
+        fn on_start(&mut self, args: &mut MainArgs) {
+            args.broadcast(&self.listeners, |_, listener| listener.hear());
+        }
+
+        fn dependencies() -> Vec<Dependency> {
+            vec![Dependency::any::<Listener>()]
+        }
+    }
+
+    /// Panics on hearing a broadcast if its label says so
+    struct Listener {
+        label: String,
+    }
+
+    impl UniquelyNamed for Listener {
+        fn name() -> &'static str {
+            "Listener"
+        }
+    }
+
+    register_actor!(Listener);
+
+    impl Listener {
+        fn hear(&self) {
+            record(&self.label, "heard");
+            if self.label == "panicking listener" {
+                panic!("{} panicked on purpose", self.label);
+            }
+        }
+    }
+
+    impl Actor for Listener {
+        type Config = String;
+
+        fn init(_args: InitArgs<Self>, label: String) -> anyhow::Result<Self> {
+            Ok(Self { label })
+        }
+    }
+
+    #[test]
+    fn broadcast_past_panic() {
+        let mut config = config(&[("Broadcaster", 1), ("Listener", 1), ("Listener", 1)]);
+        for (actor, label) in config.root.actors[1..]
+            .iter_mut()
+            .zip(["panicking listener", "listener"])
+        {
+            actor.config = serde_value::Value::String(label.into());
+            actor.supervision = Some(Supervision::Stop);
+        }
+        try_run(config).unwrap();
+        assert_eq!(events("panicking listener"), ["heard"]);
+        // the broadcast is one message on their context, which carries on past the panic
+        assert_eq!(events("listener"), ["heard"]);
+    }
+}
@@ -0,0 +1,178 @@
+use crate::{
+    context::ActorId,
+    lookup::{ActorTree, DependenceRelation, DependencyKind},
+    Config, Error,
+};
+
+use super::{create_context_args, graph, scope};
+
+/// Checks a config as far as possible without constructing any actors or starting any threads.
+///
+/// This covers scopes, context ids, thread affinities, typenames, actor configs and the lookups
+/// actors declare through [`crate::Actor::dependencies`]. Lookups that aren't declared can only be
+/// checked by running the system.
+pub fn validate(config: Config) -> Result<(), Error> {
+    let args = create_context_args(config)?;
+    let tree = args[0].tree.as_ref().unwrap().snapshot();
+    for a in args {
+        a.discard();
+    }
+    check_dependencies(&tree)
+}
+
+pub(crate) fn check_dependencies(tree: &ActorTree) -> Result<(), Error> {
+    let mut relations = Vec::new();
+    for actor in tree.actors.iter() {
+        for dependency in (actor.vtable.dependencies)() {
+            let found = dependency.resolve(tree, actor.id);
+            let fail = |reason: String| Error::Dependency {
+                actor: scope::actor_info_in_tree(tree, actor.id),
+                dependency: dependency.type_name(),
+                reason,
+            };
+            match dependency.kind {
+                DependencyKind::Any => {}
+                DependencyKind::AtMostOne => {
+                    if found.len() > 1 {
+                        return Err(fail(format!(
+                            "expected at most one match, found {}",
+                            found.len()
+                        )));
+                    }
+                }
+                DependencyKind::ExactlyOne => {
+                    if found.len() != 1 {
+                        return Err(fail(format!(
+                            "expected exactly one match, found {}",
+                            found.len()
+                        )));
+                    }
+                }
+                DependencyKind::AcyclicLocal => {
+                    let [(id, context)] = found[..] else {
+                        return Err(fail(format!(
+                            "expected exactly one match, found {}",
+                            found.len()
+                        )));
+                    };
+                    if context != actor.loc.context_id {
+                        return Err(fail(format!(
+                            "the match is on context {}, but it must be on the same context",
+                            context.0
+                        )));
+                    }
+                    if id == actor.id {
+                        return Err(fail("the only match is the actor itself".into()));
+                    }
+                    relations.push(DependenceRelation {
+                        from: actor.id,
+                        to: id,
+                    });
+                }
+            }
+        }
+    }
+
+    if let Some(cycle) = graph::find_cycle(&relations) {
+        let first: ActorId = cycle[0];
+        return Err(Error::Cycle {
+            context: tree.actors[first.as_index()].loc.context_id,
+            cycle: cycle
+                .into_iter()
+                .map(|actor| scope::actor_info_in_tree(tree, actor))
+                .collect(),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        lookup::Dependency,
+        register_actor,
+        runtime::{test::config, try_run},
+        Actor, ContextId, InitArgs, UniquelyNamed,
+    };
+
+    /// Each of these needs the other constructed first
+    struct Chicken;
+    struct Egg;
+
+    impl UniquelyNamed for Chicken {
+        fn name() -> &'static str {
+            "Chicken"
+        }
+    }
+
+    impl UniquelyNamed for Egg {
+        fn name() -> &'static str {
+            "Egg"
+        }
+    }
+
+    register_actor!(Chicken);
+    register_actor!(Egg);
+
+    impl Actor for Chicken {
+        type Config = ();
+
+        fn init(_args: InitArgs<Self>, _config: ()) -> anyhow::Result<Self> {
+            unreachable!("validation should have failed")
+        }
+
+        fn dependencies() -> Vec<Dependency> {
+            vec![Dependency::acyclic_local::<Egg>()]
+        }
+    }
+
+    impl Actor for Egg {
+        type Config = ();
+
+        fn init(_args: InitArgs<Self>, _config: ()) -> anyhow::Result<Self> {
+            unreachable!("validation should have failed")
+        }
+
+        fn dependencies() -> Vec<Dependency> {
+            vec![Dependency::acyclic_local::<Chicken>()]
+        }
+    }
+
+    #[test]
+    fn cycle() {
+        let expected = "Cycle detected in context 1: Chicken -> Egg -> Chicken \
+                        (Chicken: #0 in scope <root>, Egg: #1 in scope <root>)";
+        let err = validate(config(&[("Chicken", 1), ("Egg", 1)])).unwrap_err();
+        let Error::Cycle { context, cycle } = &err else {
+            panic!("expected a cycle, got {err}");
+        };
+        assert_eq!(*context, ContextId::new(1).unwrap());
+        let typenames: Vec<_> = cycle.iter().map(|actor| &*actor.typename).collect();
+        assert_eq!(typenames, ["Chicken", "Egg", "Chicken"]);
+        assert_eq!(err.to_string(), expected);
+
+        // running checks the same before constructing anything
+        let err = try_run(config(&[("Chicken", 1), ("Egg", 1)])).unwrap_err();
+        assert_eq!(err.to_string(), expected);
+    }
+
+    #[test]
+    fn unsatisfied_dependencies() {
+        let err = validate(config(&[("Chicken", 1)])).unwrap_err();
+        assert!(
+            matches!(&err, Error::Dependency { dependency, .. } if dependency.ends_with("Egg"))
+        );
+        assert!(err
+            .to_string()
+            .ends_with(": expected exactly one match, found 0"));
+
+        let err = validate(config(&[("Chicken", 1), ("Egg", 2)])).unwrap_err();
+        assert!(err
+            .to_string()
+            .ends_with("the match is on context 2, but it must be on the same context"));
+
+        let err = validate(config(&[("Chicken", 1), ("Missing", 1)])).unwrap_err();
+        assert!(matches!(err, Error::UnknownTypename(_)));
+    }
+}
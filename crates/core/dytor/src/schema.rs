@@ -0,0 +1,88 @@
+use schemars::{generate::SchemaSettings, json_schema, Schema};
+
+use crate::{Config, Registry};
+
+/// JSON Schema for [`crate::Config`], checking each actor's `config` against the `Config` type of
+/// the actor named by its `typename`. Only actors registered so far are included.
+///
+/// The config types derive their schemas; the derived `DytorActorConfig` is then narrowed down to
+/// one variant per registered actor type.
+pub fn config_schema() -> Schema {
+    let registry = Registry::get();
+    let mut generator = SchemaSettings::draft2020_12().into_generator();
+
+    let mut vtables: Vec<_> = registry.actor_types.values().collect();
+    vtables.sort_by_key(|vtable| (vtable.name)());
+    let configs: Vec<_> = vtables
+        .into_iter()
+        .map(|vtable| ((vtable.name)(), (vtable.config_schema)(&mut generator)))
+        .collect();
+
+    let mut schema = generator.into_root_schema_for::<Config>();
+    let defs = schema
+        .get_mut("$defs")
+        .and_then(|defs| defs.as_object_mut())
+        .unwrap();
+    let actor = defs["DytorActorConfig"].clone();
+    let actors: Vec<_> = configs
+        .into_iter()
+        .map(|(typename, config)| {
+            let mut actor = actor.clone();
+            let properties = actor["properties"].as_object_mut().unwrap();
+            properties.insert(
+                "typename".into(),
+                json_schema!({ "const": typename }).into(),
+            );
+            properties.insert("config".into(), config.into());
+            actor
+        })
+        .collect();
+    defs.insert(
+        "DytorActorConfig".into(),
+        json_schema!({ "oneOf": actors }).into(),
+    );
+    schema.insert("title".into(), "dytor::Config".into());
+    schema
+}
+
+/// JSON Schema for the `config` of the actor registered as `typename`
+pub fn actor_config_schema(typename: &str) -> Option<Schema> {
+    let (_, vtable) = Registry::get().by_name(typename)?;
+    let mut generator = SchemaSettings::draft2020_12().into_generator();
+    let mut schema = (vtable.config_schema)(&mut generator);
+    let defs = generator.take_definitions(true);
+    if !defs.is_empty() {
+        schema.insert("$defs".into(), defs.into());
+    }
+    schema.insert(
+        "$schema".into(),
+        "https://json-schema.org/draft/2020-12/schema".into(),
+    );
+    Some(schema)
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    #[test]
+    fn actor_config_per_typename() {
+        let schema = super::config_schema();
+        let actors = schema.as_value()["$defs"]["DytorActorConfig"]["oneOf"]
+            .as_array()
+            .unwrap();
+        let session = actors
+            .iter()
+            .find(|actor| actor["properties"]["typename"]["const"] == "Session")
+            .unwrap();
+        assert_eq!(session["properties"]["config"]["type"], "integer");
+        assert_eq!(
+            session["required"],
+            json!(["typename", "config", "context"])
+        );
+        assert_eq!(
+            session["properties"]["supervision"]["anyOf"][0]["$ref"],
+            "#/$defs/DytorSupervision"
+        );
+    }
+}
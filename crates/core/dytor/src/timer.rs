@@ -0,0 +1,350 @@
+//! Per-context timers, scheduled with [`crate::MainArgs::schedule_after`] and friends.
+//!
+//! Each context keeps a hashed timer wheel: a ring of slots, each covering one tick. A timer goes
+//! in the slot for the tick its deadline rounds up to, so timers never fire early, and late by at
+//! most a tick plus however long the context takes to get round to them. Timers more than one
+//! rotation away share slots with nearer ones and are skipped until their tick comes round.
+//!
+//! An actor's timers are cancelled when it stops, whether by [`crate::MainArgs::stop`] or its
+//! [`crate::config::Supervision`], so a periodic timer doesn't keep the system up on its own.
+
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    arena::Offset,
+    context::{Context, ContextId},
+};
+
+const SLOTS: u64 = 256;
+const TICK: Duration = Duration::from_millis(1);
+
+/// Refers to a timer armed on a particular context. It can only be cancelled from that context.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TimerHandle {
+    pub(crate) context: ContextId,
+    pub(crate) id: u64,
+}
+
+pub(crate) type Callback = Box<dyn FnMut(&mut Context)>;
+
+struct Timer {
+    tick: u64,
+    deadline: Instant,
+    period: Option<Duration>,
+    /// The actor the callback calls, if it's on this context
+    target: Option<Offset>,
+    callback: Callback,
+}
+
+pub(crate) struct TimerWheel {
+    start: Instant,
+    /// Every tick before this one has been fired
+    current: u64,
+    /// Indexed by `tick % SLOTS`. Ids of cancelled timers are removed lazily.
+    slots: Box<[Vec<u64>]>,
+    timers: HashMap<u64, Timer>,
+    /// Ids of the armed timers that call each actor, to cancel them when it stops
+    by_target: HashMap<Offset, HashSet<u64>>,
+    next_id: u64,
+    /// The periodic timer whose callback is running, its target, and whether it's been cancelled
+    /// meanwhile
+    firing: Option<(u64, Option<Offset>, bool)>,
+    /// What [`Self::next_deadline`] last found, lowered as timers are armed. Cleared when a timer
+    /// is cancelled or fired, since it may have been the earliest one.
+    next_tick: Option<u64>,
+}
+
+/// A timer that's due, taken out of the wheel so its callback can borrow the whole context
+pub(crate) struct Fired {
+    id: u64,
+    timer: Timer,
+}
+
+impl TimerWheel {
+    pub(crate) fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            current: 0,
+            slots: (0..SLOTS).map(|_| Vec::new()).collect(),
+            timers: HashMap::new(),
+            by_target: HashMap::new(),
+            next_id: 0,
+            firing: None,
+            next_tick: None,
+        }
+    }
+
+    /// Number of armed timers. Each one counts as an unhandled event, so a context with timers
+    /// armed isn't shut down.
+    pub(crate) fn len(&self) -> usize {
+        self.timers.len()
+    }
+
+    pub(crate) fn insert(
+        &mut self,
+        deadline: Instant,
+        period: Option<Duration>,
+        target: Option<Offset>,
+        callback: Callback,
+    ) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.arm(
+            id,
+            Timer {
+                tick: 0,
+                deadline,
+                period,
+                target,
+                callback,
+            },
+        );
+        id
+    }
+
+    /// Puts `timer` in the slot for its deadline
+    fn arm(&mut self, id: u64, mut timer: Timer) {
+        let since_start = timer.deadline.saturating_duration_since(self.start);
+        let tick = since_start.as_nanos().div_ceil(TICK.as_nanos()) as u64;
+        timer.tick = tick.max(self.current);
+        self.next_tick = self.next_tick.map(|next| next.min(timer.tick));
+        self.slots[(timer.tick % SLOTS) as usize].push(id);
+        if let Some(target) = timer.target {
+            self.by_target.entry(target).or_default().insert(id);
+        }
+        self.timers.insert(id, timer);
+    }
+
+    /// Takes a timer out of the wheel, when it's cancelled or fired
+    fn remove(&mut self, id: u64) -> Option<Timer> {
+        let timer = self.timers.remove(&id)?;
+        self.next_tick = None;
+        if let Some(target) = timer.target {
+            let ids = self.by_target.get_mut(&target).unwrap();
+            ids.remove(&id);
+            if ids.is_empty() {
+                self.by_target.remove(&target);
+            }
+        }
+        Some(timer)
+    }
+
+    /// Returns `false` if the timer has already fired or been cancelled
+    pub(crate) fn cancel(&mut self, id: u64) -> bool {
+        if self.remove(id).is_some() {
+            return true;
+        }
+        match &mut self.firing {
+            Some((firing, _, cancelled)) if *firing == id && !*cancelled => {
+                *cancelled = true;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Cancels every timer that calls the actor at `target`, once it has stopped
+    pub(crate) fn cancel_target(&mut self, target: Offset) {
+        for id in self.by_target.remove(&target).unwrap_or_default() {
+            self.timers.remove(&id);
+            self.next_tick = None;
+        }
+        if let Some((_, Some(firing), cancelled)) = &mut self.firing {
+            *cancelled |= *firing == target;
+        }
+    }
+
+    /// The earliest time a timer may be due, or `None` if there are no timers. This may be a tick
+    /// with nothing to fire if every timer is more than a rotation away. The slots are only
+    /// scanned again once a timer has been cancelled or fired.
+    pub(crate) fn next_deadline(&mut self) -> Option<Instant> {
+        if self.timers.is_empty() {
+            return None;
+        }
+        let tick = match self.next_tick {
+            // a rotation's worth of empty slots may have gone by since
+            Some(tick) if tick >= self.current => tick,
+            _ => {
+                let tick = (self.current..self.current + SLOTS)
+                    .find(|&tick| self.slot_has_due(tick))
+                    .unwrap_or(self.current + SLOTS);
+                *self.next_tick.insert(tick)
+            }
+        };
+        Some(self.start + Duration::from_nanos(TICK.as_nanos() as u64 * tick))
+    }
+
+    fn slot_has_due(&self, tick: u64) -> bool {
+        self.slots[(tick % SLOTS) as usize]
+            .iter()
+            .any(|id| self.timers.get(id).is_some_and(|timer| timer.tick == tick))
+    }
+
+    pub(crate) fn pop_due(&mut self) -> Option<Fired> {
+        if self.timers.is_empty() {
+            return None;
+        }
+        let now = now_tick(self.start, Instant::now());
+        while self.current <= now {
+            let tick = self.current;
+            let slot = &mut self.slots[(tick % SLOTS) as usize];
+            let timers = &self.timers;
+            slot.retain(|id| timers.contains_key(id));
+            if let Some(pos) = slot.iter().position(|id| timers[id].tick == tick) {
+                let id = slot.swap_remove(pos);
+                let timer = self.remove(id).unwrap();
+                return Some(Fired { id, timer });
+            }
+            self.current += 1;
+        }
+        None
+    }
+}
+
+fn now_tick(start: Instant, now: Instant) -> u64 {
+    (now.saturating_duration_since(start).as_nanos() / TICK.as_nanos()) as u64
+}
+
+impl Fired {
+    /// Runs the callback, then re-arms the timer if it's periodic and wasn't cancelled by its own
+    /// callback. Missed periods are skipped rather than fired in a burst.
+    pub(crate) fn fire(self, ctx: &mut Context) {
+        let Fired { id, mut timer } = self;
+        if timer.period.is_none() {
+            (timer.callback)(ctx);
+            return;
+        }
+
+        ctx.data.timers.firing = Some((id, timer.target, false));
+        (timer.callback)(ctx);
+        let Some((_, _, cancelled)) = ctx.data.timers.firing.take() else {
+            unreachable!()
+        };
+        if cancelled {
+            return;
+        }
+
+        let period = timer.period.unwrap();
+        timer.deadline += period;
+        let now = Instant::now();
+        if timer.deadline <= now {
+            timer.deadline = now + period;
+        }
+        ctx.data.timers.arm(id, timer);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::mpsc, thread};
+
+    use super::*;
+    use crate::{
+        lookup::Key,
+        register_actor,
+        runtime::{test::config, try_run},
+        Actor, InitArgs, MainArgs, UniquelyNamed,
+    };
+
+    fn noop() -> Callback {
+        Box::new(|_| {})
+    }
+
+    #[test]
+    fn fires_in_deadline_order() {
+        let mut wheel = TimerWheel::new();
+        let start = wheel.start;
+        let late = wheel.insert(start + Duration::from_millis(3), None, None, noop());
+        let early = wheel.insert(start + Duration::from_millis(1), None, None, noop());
+        // more than a rotation away, so it shares a slot with `early`
+        let far = wheel.insert(start + TICK * (SLOTS as u32 + 1), None, None, noop());
+        assert_eq!(wheel.next_deadline(), Some(start + TICK));
+
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(wheel.pop_due().map(|fired| fired.id), Some(early));
+        assert_eq!(wheel.pop_due().map(|fired| fired.id), Some(late));
+        assert!(wheel.pop_due().is_none());
+        assert_eq!(wheel.len(), 1);
+        assert!(wheel.next_deadline().unwrap() > start + Duration::from_millis(3));
+        assert!(wheel.cancel(far));
+        assert!(!wheel.cancel(far));
+        assert_eq!(wheel.next_deadline(), None);
+    }
+
+    #[test]
+    fn next_deadline_follows_arm_and_cancel() {
+        let mut wheel = TimerWheel::new();
+        let start = wheel.start;
+        let late = wheel.insert(start + Duration::from_millis(50), None, None, noop());
+        assert_eq!(wheel.next_deadline(), Some(start + TICK * 50));
+        let early = wheel.insert(start + Duration::from_millis(20), None, None, noop());
+        assert_eq!(wheel.next_deadline(), Some(start + TICK * 20));
+        assert!(wheel.cancel(early));
+        assert_eq!(wheel.next_deadline(), Some(start + TICK * 50));
+        assert!(wheel.cancel(late));
+        assert_eq!(wheel.next_deadline(), None);
+    }
+
+    #[test]
+    fn cancel_target() {
+        let mut wheel = TimerWheel::new();
+        let start = wheel.start;
+        let (a, b) = (Offset::new(0), Offset::new(8));
+        let period = Some(Duration::from_millis(1));
+        wheel.insert(start + TICK, period, Some(a), noop());
+        wheel.insert(start + TICK * 2, None, Some(a), noop());
+        let kept = wheel.insert(start + TICK * 3, None, Some(b), noop());
+        wheel.cancel_target(a);
+        assert_eq!(wheel.len(), 1);
+        assert!(wheel.cancel(kept));
+        assert!(wheel.by_target.is_empty());
+    }
+
+    /// Stops itself from its own periodic timer after a few ticks
+    struct Ticker {
+        me: Key<Ticker>,
+        ticks: u32,
+    }
+
+    impl UniquelyNamed for Ticker {
+        fn name() -> &'static str {
+            "Ticker"
+        }
+    }
+
+    register_actor!(Ticker);
+
+    impl Actor for Ticker {
+        type Config = ();
+
+        fn init(args: InitArgs<Self>, _config: ()) -> anyhow::Result<Self> {
+            Ok(Self {
+                me: args.key(),
+                ticks: 0,
+            })
+        }
+
+        fn on_start(&mut self, args: &mut MainArgs) {
+            args.schedule_every(self.me, Duration::from_millis(1), |args, ticker| {
+                ticker.ticks += 1;
+                if ticker.ticks == 3 {
+                    args.stop(ticker.me);
+                }
+            });
+        }
+    }
+
+    #[test]
+    fn stopping_cancels_timers() {
+        // the periodic timer would keep the system up forever if it outlived the ticker
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            try_run(config(&[("Ticker", 1)])).unwrap();
+            tx.send(()).unwrap();
+        });
+        rx.recv_timeout(Duration::from_secs(30)).unwrap();
+    }
+}
@@ -0,0 +1,188 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{
+    spanned::Spanned, Data, DeriveInput, Fields, GenericArgument, LitStr, Path, PathArguments, Type,
+};
+
+enum Lookup {
+    Key,
+    OptionalKey,
+    Keys,
+    Accessor,
+    Accessors,
+    BroadcastGroup,
+    AcyclicLocalKey,
+}
+
+struct Field {
+    ident: syn::Ident,
+    lookup: Lookup,
+    target: Type,
+    name: Option<LitStr>,
+    scope: Option<LitStr>,
+}
+
+pub(crate) fn expand(input: DeriveInput) -> syn::Result<TokenStream> {
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new(
+            input.generics.span(),
+            "Dependencies can't be derived for generic structs",
+        ));
+    }
+    let krate = crate_path(&input)?;
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new(
+            input.ident.span(),
+            "Dependencies can only be derived for structs",
+        ));
+    };
+    let Fields::Named(fields) = &data.fields else {
+        return Err(syn::Error::new(
+            data.fields.span(),
+            "Dependencies can only be derived for structs with named fields",
+        ));
+    };
+    let fields = fields
+        .named
+        .iter()
+        .map(parse_field)
+        .collect::<syn::Result<Vec<_>>>()?;
+
+    let dependencies = fields.iter().map(|field| {
+        let target = &field.target;
+        let constructor = match field.lookup {
+            Lookup::Key | Lookup::Accessor => quote!(exactly_one),
+            Lookup::OptionalKey => quote!(at_most_one),
+            Lookup::Keys | Lookup::Accessors | Lookup::BroadcastGroup => quote!(any),
+            Lookup::AcyclicLocalKey => quote!(acyclic_local),
+        };
+        let filters = filters(field);
+        quote!(#krate::lookup::Dependency::#constructor::<#target>() #filters)
+    });
+
+    let grabs = fields.iter().map(|field| {
+        let ident = &field.ident;
+        let target = &field.target;
+        let filters = filters(field);
+        let call = match field.lookup {
+            Lookup::Key => quote!(exactly_one_key()),
+            Lookup::OptionalKey => quote!(optional_key()),
+            Lookup::Keys => quote!(all_keys().collect()),
+            Lookup::Accessor => quote!(exactly_one_accessor()),
+            Lookup::Accessors => quote!(all_accessors().collect()),
+            Lookup::BroadcastGroup => quote!(broadcast_group()),
+            Lookup::AcyclicLocalKey => quote!(acyclic_local_key()),
+        };
+        quote!(#ident: self.query::<#target>() #filters .#call)
+    });
+
+    let name = &input.ident;
+    Ok(quote! {
+        impl #krate::lookup::Dependencies for #name {
+            fn list() -> ::std::vec::Vec<#krate::lookup::Dependency> {
+                ::std::vec![#(#dependencies),*]
+            }
+        }
+
+        impl<ActorT> #krate::Grab<#name> for #krate::InitArgs<'_, ActorT> {
+            fn grab(&mut self) -> #name {
+                #name {
+                    #(#grabs,)*
+                }
+            }
+        }
+    })
+}
+
+fn filters(field: &Field) -> TokenStream {
+    let name = field.name.iter();
+    let scope = field.scope.iter();
+    quote!(#(.named(#name))* #(.in_scope(#scope))*)
+}
+
+fn crate_path(input: &DeriveInput) -> syn::Result<Path> {
+    let mut res = syn::parse_quote!(::dytor);
+    for attr in &input.attrs {
+        if !attr.path().is_ident("dytor") {
+            continue;
+        }
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("crate") {
+                res = meta.value()?.parse::<LitStr>()?.parse()?;
+                Ok(())
+            } else {
+                Err(meta.error("expected `crate`"))
+            }
+        })?;
+    }
+    Ok(res)
+}
+
+fn parse_field(field: &syn::Field) -> syn::Result<Field> {
+    let mut name = None;
+    let mut scope = None;
+    for attr in &field.attrs {
+        if !attr.path().is_ident("dytor") {
+            continue;
+        }
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                name = Some(meta.value()?.parse()?);
+                Ok(())
+            } else if meta.path.is_ident("scope") {
+                scope = Some(meta.value()?.parse()?);
+                Ok(())
+            } else {
+                Err(meta.error("expected `name` or `scope`"))
+            }
+        })?;
+    }
+
+    let unsupported = || {
+        syn::Error::new(
+            field.ty.span(),
+            "expected Key<T>, Option<Key<T>>, Vec<Key<T>>, Accessor<T>, Vec<Accessor<T>>, \
+             BroadcastGroup<T> or AcyclicLocalKey<T>",
+        )
+    };
+    let (outer, arg) = split(&field.ty).ok_or_else(unsupported)?;
+    let (lookup, target) = match outer.as_str() {
+        "Key" => (Lookup::Key, arg),
+        "Accessor" => (Lookup::Accessor, arg),
+        "BroadcastGroup" => (Lookup::BroadcastGroup, arg),
+        "AcyclicLocalKey" => (Lookup::AcyclicLocalKey, arg),
+        "Option" | "Vec" => {
+            let (inner, arg) = split(arg).ok_or_else(unsupported)?;
+            match (outer.as_str(), inner.as_str()) {
+                ("Option", "Key") => (Lookup::OptionalKey, arg),
+                ("Vec", "Key") => (Lookup::Keys, arg),
+                ("Vec", "Accessor") => (Lookup::Accessors, arg),
+                _ => return Err(unsupported()),
+            }
+        }
+        _ => return Err(unsupported()),
+    };
+
+    Ok(Field {
+        ident: field.ident.clone().unwrap(),
+        lookup,
+        target: target.clone(),
+        name,
+        scope,
+    })
+}
+
+/// Splits `Outer<T>` into the last segment of `Outer` and `T`
+fn split(ty: &Type) -> Option<(String, &Type)> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+    let last = path.path.segments.last()?;
+    let PathArguments::AngleBracketed(args) = &last.arguments else {
+        return None;
+    };
+    let [GenericArgument::Type(arg)] = &args.args.iter().collect::<Vec<_>>()[..] else {
+        return None;
+    };
+    Some((last.ident.to_string(), arg))
+}
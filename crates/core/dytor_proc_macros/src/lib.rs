@@ -2,6 +2,8 @@ use proc_macro::TokenStream;
 use quote::quote;
 use syn::{parse_macro_input, DeriveInput};
 
+mod dependencies;
+
 #[proc_macro_derive(UniquelyNamed)]
 pub fn uniquely_named_derive(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
@@ -15,3 +17,13 @@ pub fn uniquely_named_derive(input: TokenStream) -> TokenStream {
     };
     TokenStream::from(expanded)
 }
+
+/// Implements `dytor::Dependencies` and `dytor::Grab` for a struct whose fields are all lookups.
+/// See `dytor::lookup::Dependencies` for the supported field types and attributes.
+#[proc_macro_derive(Dependencies, attributes(dytor))]
+pub fn dependencies_derive(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    dependencies::expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
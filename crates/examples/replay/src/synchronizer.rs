@@ -2,6 +2,7 @@ use std::cmp::Ordering;
 use std::collections::BinaryHeap;
 
 use common::anyhow::Result;
+use common::dytor::lookup::Dependency;
 use common::dytor::{register_actor, Accessor, Actor, InitArgs, UniquelyNamed};
 use tokio::sync::oneshot;
 pub use tokio_stream::StreamExt;
@@ -23,6 +24,10 @@ impl Actor for Synchronizer {
         runtime.spawn_with(move || background_task(sources, runtime2));
         Ok(Self {})
     }
+
+    fn dependencies() -> Vec<Dependency> {
+        vec![Dependency::any::<dyn Producer>()]
+    }
 }
 struct HeapEntry {
     next_event: Event<UntypedBox>,
@@ -67,7 +72,8 @@ async fn background_task(producers: Vec<Accessor<dyn Producer>>, runtime: TokioS
             acc.send(|args, p| {
                 tx.send(p.create_stream(args, runtime))
                     .unwrap_or_else(|_| panic!("Could not send"))
-            });
+            })
+            .unwrap();
             (acc, rx)
         })
         .collect();
@@ -111,7 +117,8 @@ async fn background_task(producers: Vec<Accessor<dyn Producer>>, runtime: TokioS
                     obj,
                 },
             )
-        });
+        })
+        .unwrap();
 
         let ev = stream.next().await;
         if let Some(ev) = ev {
@@ -8,9 +8,10 @@ use replay::{tokio, Event, Producer, TokioSingleThread, TypedProducer};
 use common::anyhow;
 use common::chrono::DateTime;
 use common::dytor::lookup::BroadcastGroup;
-use common::dytor::{register_actor, Actor, InitArgs, MainArgs, UniquelyNamed};
+use common::dytor::{register_actor, Actor, Dependencies, Grab, InitArgs, MainArgs, UniquelyNamed};
 
-#[derive(UniquelyNamed)]
+#[derive(UniquelyNamed, Dependencies)]
+#[dytor(crate = "common::dytor")]
 pub struct IntervalUnitProducer {
     consumers: BroadcastGroup<IntervalUnitConsumer>,
 }
@@ -42,9 +43,7 @@ impl Actor for IntervalUnitProducer {
     type Config = ();
 
     fn init(mut args: InitArgs<Self>, _config: ()) -> anyhow::Result<Self> {
-        Ok(Self {
-            consumers: args.query().broadcast_group(),
-        })
+        Ok(args.grab())
     }
 }
 